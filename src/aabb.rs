@@ -1,7 +1,7 @@
 use std::simd::{f32x2, Simd, f64x2};
 use std::mem;
 
-use crate::{vec3::Point3, ray::Ray};
+use crate::{vec3::{Point3, Vec3}, ray::Ray};
 
 #[derive(Clone, Copy)]
 pub struct AABB {
@@ -14,7 +14,7 @@ impl AABB {
         AABB { minimum: *minimum, maximum: *maximum }
     }
 
-    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    pub fn hit(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
         for a in 0..3 {
 
             let b: f64x2 = Simd::from_array([self.minimum[a], self.maximum[a]]);
@@ -29,10 +29,12 @@ impl AABB {
             let t0 = f64::min(y[0], y[1]);
             let t1 = f64::max(y[0], y[1]);
 
-            let _t_min = f64::max(t0, t_min);
-            let _t_max = f64::min(t1, t_max);
+            // the interval has to shrink across all three slabs, otherwise a ray passing each slab at
+            // different times still counts as a hit
+            t_min = f64::max(t0, t_min);
+            t_max = f64::min(t1, t_max);
 
-            if _t_max <= _t_min {
+            if t_max <= t_min {
                 return false;
             }
         }
@@ -40,6 +42,37 @@ impl AABB {
         true
     }
 
+    // same slab test, with the reciprocal of the ray direction computed once by the caller
+    pub fn hit_inv(&self, origin: &Point3, inv_dir: &Vec3, mut t_min: f64, mut t_max: f64) -> bool {
+        for a in 0..3 {
+            let mut t0 = (self.minimum[a] - origin[a]) * inv_dir[a];
+            let mut t1 = (self.maximum[a] - origin[a]) * inv_dir[a];
+
+            if t0 > t1 {
+                mem::swap(&mut t0, &mut t1);
+            }
+
+            t_min = f64::max(t0, t_min);
+            t_max = f64::min(t1, t_max);
+
+            if t_max <= t_min {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.minimum + self.maximum)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.maximum - self.minimum;
+
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
     pub fn surrounding_box(box0: &AABB, box1: &AABB) -> AABB {
         let small = Point3::new(
             f64::min(box0.minimum.x, box1.minimum.x),
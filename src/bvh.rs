@@ -1,8 +1,11 @@
 use std::f64;
-use std::cmp::Ordering;
+use clap::ValueEnum;
 use crate::ray::Ray;
-use crate::hittable::{Hit, HitRecord};
+use crate::hittable::{Hit, HitRecord, World};
 use crate::aabb::AABB;
+use crate::vec3::Point3;
+use crate::linear_bvh::LinearBVH;
+use crate::error::{Error, Result};
 
 enum BVHNode {
     Branch { left: Box<BVH>, right: Box<BVH> },
@@ -14,57 +17,222 @@ pub struct BVH {
     bbox: AABB
 }
 
-impl BVH {
-    pub fn new(mut hitable: Vec<Box<dyn Hit>>, time0: f64, time1: f64) -> Self {
-        fn box_compare(time0: f64, time1: f64, axis: u8) -> impl FnMut(&Box<dyn Hit>, &Box<dyn Hit>) -> Ordering {
-            move |a, b| {
-                let a_bbox = a.bounding_box(time0, time1);
-                let b_bbox = b.bounding_box(time0, time1);
-                if let (Some(a), Some(b)) = (a_bbox, b_bbox) {
-                    let ac = a.minimum[axis] + a.maximum[axis];
-                    let bc = b.minimum[axis] + b.maximum[axis];
-                    ac.partial_cmp(&bc).unwrap()
-                } else {
-                    panic!["no bounding box in bvh node"]
-                }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SplitMethod {
+    /// split at the median primitive along the longest axis
+    Median,
+    /// binned surface area heuristic, slower to build but much faster to trace on uneven meshes
+    Sah
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BVHLayout {
+    /// tree of boxed nodes
+    Tree,
+    /// flattened depth first array, see LinearBVH
+    Flat
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BVHOptions {
+    pub split: SplitMethod,
+    /// most primitives a single leaf may hold
+    pub leaf_size: usize,
+    pub layout: BVHLayout
+}
+
+impl Default for BVHOptions {
+    fn default() -> Self {
+        BVHOptions { split: SplitMethod::Median, leaf_size: 1, layout: BVHLayout::Tree }
+    }
+}
+
+impl BVHOptions {
+    // builds whichever bvh `layout` asks for
+    pub fn build(&self, hitable: World, time0: f64, time1: f64) -> Result<Box<dyn Hit>> {
+        Ok(match self.layout {
+            BVHLayout::Tree => Box::new(BVH::with_options(hitable, time0, time1, self)?),
+            BVHLayout::Flat => Box::new(LinearBVH::new(hitable, time0, time1, self)?)
+        })
+    }
+}
+
+// primitive with its bounds computed once up front, so building never calls bounding_box again
+pub(crate) struct BuildPrim {
+    pub bbox: AABB,
+    pub centroid: Point3,
+    pub object: Box<dyn Hit>
+}
+
+impl BuildPrim {
+    pub(crate) fn from_world(hitable: World, time0: f64, time1: f64) -> Result<Vec<BuildPrim>> {
+        if hitable.is_empty() {
+            return Err(Error::EmptyScene)
+        }
+
+        hitable.into_iter().map(|object| {
+            let bbox = object.bounding_box(time0, time1).ok_or(Error::Unbounded)?;
+
+            Ok(BuildPrim { bbox, centroid: bbox.centroid(), object })
+        }).collect()
+    }
+}
+
+const SAH_BINS: usize = 16;
+// cost of visiting a node relative to intersecting one primitive
+const TRAVERSAL_COST: f64 = 1.0;
+
+pub(crate) fn bounds(prims: &[BuildPrim]) -> AABB {
+    prims.iter().skip(1).fold(prims[0].bbox, |acc, p| AABB::surrounding_box(&acc, &p.bbox))
+}
+
+fn centroid_bounds(prims: &[BuildPrim]) -> AABB {
+    prims.iter().skip(1).fold(AABB::new(&prims[0].centroid, &prims[0].centroid), |acc, p| AABB::surrounding_box(&acc, &AABB::new(&p.centroid, &p.centroid)))
+}
+
+// reorders `prims` so everything going to the left child comes first and returns where the right child
+// starts along with the split axis, None means `prims` should become a single leaf
+pub(crate) fn partition(prims: &mut [BuildPrim], options: &BVHOptions) -> Option<(usize, u8)> {
+    let len = prims.len();
+
+    if len <= 1 {
+        return None
+    }
+
+    match options.split {
+        SplitMethod::Median => {
+            if len <= options.leaf_size {
+                return None
             }
+
+            let bbox = bounds(prims);
+            let axis = (0..3u8).max_by(|a, b| {
+                (bbox.maximum[*a] - bbox.minimum[*a]).total_cmp(&(bbox.maximum[*b] - bbox.minimum[*b]))
+            }).unwrap();
+
+            let mid = len / 2;
+            prims.select_nth_unstable_by(mid, |a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+
+            Some((mid, axis))
+        },
+        SplitMethod::Sah => sah_partition(prims, options.leaf_size)
+    }
+}
+
+fn sah_partition(prims: &mut [BuildPrim], leaf_size: usize) -> Option<(usize, u8)> {
+    let len = prims.len();
+    let parent_area = bounds(prims).surface_area().max(f64::MIN_POSITIVE);
+    let cbox = centroid_bounds(prims);
+
+    let bin_of = |c: f64, axis: u8| -> usize {
+        let extent = cbox.maximum[axis] - cbox.minimum[axis];
+        (((c - cbox.minimum[axis]) / extent * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+    };
+
+    // (cost, axis, last bin on the left side)
+    let mut best: Option<(f64, u8, usize)> = None;
+
+    for axis in 0..3u8 {
+        if cbox.maximum[axis] - cbox.minimum[axis] <= 0.0 {
+            continue
         }
 
-        fn axis_range(hitable: &Vec<Box<dyn Hit>>, time0: f64, time1: f64, axis: u8) -> f64 {
-            let (min, max) = hitable.iter().fold((f64::MAX, f64::MIN), |(bmin, bmax), hit| {
-                if let Some(aabb) = hit.bounding_box(time0, time1) {
-                    (bmin.min(aabb.minimum[axis]), bmax.max(aabb.maximum[axis]))
-                } else {
-                    (bmin, bmax)
-                }
+        let mut bins: [(Option<AABB>, usize); SAH_BINS] = [(None, 0); SAH_BINS];
+
+        for p in prims.iter() {
+            let bin = &mut bins[bin_of(p.centroid[axis], axis)];
+            bin.0 = Some(match bin.0 {
+                Some(b) => AABB::surrounding_box(&b, &p.bbox),
+                None => p.bbox
             });
-            max - min
+            bin.1 += 1;
+        }
+
+        // sweep from the right so the cost of every split plane is known in one pass from the left
+        let mut right_area = [0.0; SAH_BINS];
+        let mut right_count = [0; SAH_BINS];
+        let mut acc: Option<AABB> = None;
+        let mut count = 0;
+
+        for i in (1..SAH_BINS).rev() {
+            if let Some(b) = bins[i].0 {
+                acc = Some(acc.map_or(b, |a| AABB::surrounding_box(&a, &b)));
+            }
+            count += bins[i].1;
+            right_area[i - 1] = acc.map_or(0.0, |a| a.surface_area());
+            right_count[i - 1] = count;
         }
 
-        let mut axis_ranges: Vec<(u8, f64)> = (0..3)
-            .map(|a| (a, axis_range(&hitable, time0, time1, a)))
-            .collect();
+        let mut acc: Option<AABB> = None;
+        let mut count = 0;
 
-        axis_ranges.sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        for i in 0..SAH_BINS - 1 {
+            if let Some(b) = bins[i].0 {
+                acc = Some(acc.map_or(b, |a| AABB::surrounding_box(&a, &b)));
+            }
+            count += bins[i].1;
+
+            if count == 0 || right_count[i] == 0 {
+                continue
+            }
 
-        let axis = axis_ranges[0].0;
+            let left_area = acc.map_or(0.0, |a| a.surface_area());
+            let cost = TRAVERSAL_COST + (left_area * count as f64 + right_area[i] * right_count[i] as f64) / parent_area;
 
-        hitable.sort_unstable_by(box_compare(time0, time1, axis));
-        let len = hitable.len();
-        match len {
-            0 => panic!["no elements in scene"],
-            1 => {
-                let leaf = hitable.pop().unwrap();
-                if let Some(bbox) = leaf.bounding_box(time0, time1) {
-                    BVH { tree: BVHNode::Leaf(leaf), bbox }
-                } else {
-                    panic!["no bounding box in bvh node"]
+            let better = match best {
+                Some((best_cost, _, _)) => cost < best_cost,
+                None => true
+            };
+
+            if better {
+                best = Some((cost, axis, i));
+            }
+        }
+    }
+
+    match best {
+        Some((cost, axis, split)) => {
+            if len <= leaf_size && cost >= len as f64 {
+                return None
+            }
+
+            let mut mid = 0;
+            for i in 0..len {
+                if bin_of(prims[i].centroid[axis], axis) <= split {
+                    prims.swap(i, mid);
+                    mid += 1;
                 }
+            }
+
+            Some((mid, axis))
+        },
+        // every centroid is in the same spot, no plane can separate them
+        None if len <= leaf_size => None,
+        None => Some((len / 2, 0))
+    }
+}
+
+impl BVH {
+    pub fn new(hitable: Vec<Box<dyn Hit>>, time0: f64, time1: f64) -> Result<Self> {
+        BVH::with_options(hitable, time0, time1, &BVHOptions::default())
+    }
+
+    pub fn with_options(hitable: Vec<Box<dyn Hit>>, time0: f64, time1: f64, options: &BVHOptions) -> Result<Self> {
+        Ok(BVH::build(BuildPrim::from_world(hitable, time0, time1)?, options))
+    }
+
+    fn build(mut prims: Vec<BuildPrim>, options: &BVHOptions) -> Self {
+        let bbox = bounds(&prims);
+
+        match partition(&mut prims, options) {
+            None if prims.len() == 1 => BVH { tree: BVHNode::Leaf(prims.pop().unwrap().object), bbox },
+            None => {
+                let leaf: World = prims.into_iter().map(|p| p.object).collect();
+                BVH { tree: BVHNode::Leaf(Box::new(leaf)), bbox }
             },
-            _ => {
-                let right = BVH::new(hitable.drain(len / 2..).collect(), time0, time1);
-                let left = BVH::new(hitable, time0, time1);
-                let bbox = AABB::surrounding_box(&left.bbox, &right.bbox);
+            Some((mid, _)) => {
+                let right = BVH::build(prims.split_off(mid), options);
+                let left = BVH::build(prims, options);
                 BVH { tree: BVHNode::Branch { left: Box::new(left), right: Box::new(right) }, bbox }
             }
         }
@@ -88,6 +256,17 @@ impl Hit for BVH {
         }
     }
 
+    fn occluded(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        if !self.bbox.hit(ray, t_min, t_max) {
+            return false
+        }
+
+        match &self.tree {
+            BVHNode::Leaf(leaf) => leaf.occluded(ray, t_min, t_max),
+            BVHNode::Branch { left, right } => left.occluded(ray, t_min, t_max) || right.occluded(ray, t_min, t_max)
+        }
+    }
+
     fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
         Some(self.bbox)
     }
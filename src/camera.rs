@@ -1,9 +1,51 @@
-use rand::Rng;
+use std::f64::consts::PI;
 
-use crate::{vec3::{Point3, Vec3, unit_vector, cross_product, random_in_unit_disk}, ray::Ray};
+use clap::ValueEnum;
+use serde::Deserialize;
 
+use crate::{vec3::{Point3, Vec3, unit_vector, cross_product, random_in_unit_disk}, ray::Ray, sampler::Sampler};
+
+// turns a position on the image, (0, 0) bottom left to (1, 1) top right, into a primary ray,
+// None for positions the camera doesn't see (outside a fisheye's image circle), those stay black
+pub trait Camera: Send + Sync {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Projection {
+    /// thin lens pinhole camera, see PerspectiveCamera
+    Perspective,
+    /// parallel rays, objects keep their size regardless of distance
+    Orthographic,
+    /// full 360 by 180 degree panorama, use a 2:1 image
+    Equirectangular,
+    /// circular fisheye filling the image height
+    Fisheye
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FisheyeModel {
+    /// distance from the image center is proportional to the angle off axis
+    Equidistant,
+    /// equal areas on the image cover equal solid angles
+    Equisolid
+}
+
+// where a camera sits and looks, and when its shutter is open
+#[derive(Copy, Clone, Debug)]
+pub struct View {
+    pub origin: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub time0: f64,
+    pub time1: f64
+}
+
+// fixed viewport looking down -z, kept for quick experiments
 #[derive(Copy, Clone)]
-pub struct Camera {
+pub struct SimpleCamera {
     pub origin: Point3,
     pub lower_left_corner: Point3,
     pub horizontal: Vec3,
@@ -12,14 +54,11 @@ pub struct Camera {
     pub time1: f64
 }
 
-unsafe impl Sync for Camera {}
-unsafe impl Send for Camera {}
-
-impl Camera {
-    pub fn new(viewport_height: f64, viewport_width: f64, focal_length: f64, origin: Point3, time0: f64, time1: f64) -> Camera {
+impl SimpleCamera {
+    pub fn new(viewport_height: f64, viewport_width: f64, focal_length: f64, origin: Point3, time0: f64, time1: f64) -> SimpleCamera {
         let h = Vec3::new(viewport_width, 0.0, 0.0);
         let v = Vec3::new(0.0, viewport_height, 0.0);
-        Camera {
+        SimpleCamera {
             origin,
             horizontal: h,
             vertical: v,
@@ -29,16 +68,17 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(self, u: f64, v: f64) -> Ray {
-
-        let mut rng = rand::thread_rng();
+}
 
-        Ray::new(self.origin, self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin, rng.gen_range(self.time0..=self.time1))
+impl Camera for SimpleCamera {
+    fn get_ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        Some(Ray::new(self.origin, self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin, shutter_time(self.time0, self.time1, sampler)))
     }
 }
 
+// thin lens camera, `aperture` > 0 blurs everything not at `focus_dist`
 #[derive(Copy, Clone)]
-pub struct OrthographicCamera {
+pub struct PerspectiveCamera {
     pub origin: Point3,
     pub lower_left_corner: Point3,
     pub horizontal: Vec3,
@@ -49,37 +89,147 @@ pub struct OrthographicCamera {
     pub time1: f64
 }
 
-unsafe impl Sync for OrthographicCamera {}
-unsafe impl Send for OrthographicCamera {}
-
-impl OrthographicCamera {
-    pub fn new(origin: Point3, lookat: Point3, vup: Vec3, vfov: f64, aspect_ratio: f64, aperture: f64, focus_dist: f64, time0: f64, time1: f64) -> OrthographicCamera {
+impl PerspectiveCamera {
+    pub fn new(view: &View, vfov: f64, aspect_ratio: f64, aperture: f64, focus_dist: f64) -> PerspectiveCamera {
         let theta = vfov.to_radians();
         let h = (theta / 2.0).tan();
         let v_height = 2.0 * h;
         let v_width = aspect_ratio * v_height;
 
-        let w = unit_vector(&(origin - lookat));
-        let u = unit_vector(&cross_product(&vup, &w));
-        let v = cross_product(&w, &u);
+        let origin = view.origin;
+        let [u, v, w] = view_basis(origin, view.lookat, view.vup);
 
         let horizontal = focus_dist * v_width * u;
         let vertical = focus_dist * v_height * v;
         let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0 - focus_dist * w;
         let lens_radius = aperture / 2.0;
 
+        PerspectiveCamera {
+            origin, lower_left_corner, horizontal, vertical, uvw: [u, v, w], lens_radius, time0: view.time0, time1: view.time1
+        }
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let rd = self.lens_radius * random_in_unit_disk(sampler);
+        let offset = self.uvw[0] * rd.x + self.uvw[1] * rd.y;
+
+        Some(Ray::new(self.origin + offset, self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset, shutter_time(self.time0, self.time1, sampler)))
+    }
+}
+
+// parallel projection along the view direction, `view_height` is how much of the scene fits vertically
+#[derive(Copy, Clone)]
+pub struct OrthographicCamera {
+    pub lower_left_corner: Point3,
+    pub horizontal: Vec3,
+    pub vertical: Vec3,
+    pub direction: Vec3,
+    pub time0: f64,
+    pub time1: f64
+}
+
+impl OrthographicCamera {
+    pub fn new(view: &View, view_height: f64, aspect_ratio: f64) -> OrthographicCamera {
+        let [u, v, w] = view_basis(view.origin, view.lookat, view.vup);
+
+        let horizontal = aspect_ratio * view_height * u;
+        let vertical = view_height * v;
+
         OrthographicCamera {
-            origin: origin, lower_left_corner, horizontal, vertical, uvw: [u, v, w], lens_radius, time1, time0
+            lower_left_corner: view.origin - horizontal / 2.0 - vertical / 2.0, horizontal, vertical, direction: -w, time0: view.time0, time1: view.time1
         }
     }
+}
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        Some(Ray::new(self.lower_left_corner + s * self.horizontal + t * self.vertical, self.direction, shutter_time(self.time0, self.time1, sampler)))
+    }
+}
 
-        let mut rng = rand::thread_rng();
+// longitude across the image and latitude up it, the image center looks at `lookat`
+#[derive(Copy, Clone)]
+pub struct EquirectangularCamera {
+    pub origin: Point3,
+    pub uvw: [Vec3; 3],
+    pub time0: f64,
+    pub time1: f64
+}
 
-        let rd = self.lens_radius * random_in_unit_disk(&mut rng);
-        let offset = self.uvw[0] * rd.x + self.uvw[1] * rd.y;
+impl EquirectangularCamera {
+    pub fn new(view: &View) -> EquirectangularCamera {
+        EquirectangularCamera { origin: view.origin, uvw: view_basis(view.origin, view.lookat, view.vup), time0: view.time0, time1: view.time1 }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let phi = (s - 0.5) * 2.0 * PI;
+        let theta = (t - 0.5) * PI;
+        let [u, v, w] = self.uvw;
 
-        Ray::new(self.origin + offset, self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset, rng.gen_range(self.time0..=self.time1))
+        let direction = theta.cos() * (phi.sin() * u - phi.cos() * w) + theta.sin() * v;
+
+        Some(Ray::new(self.origin, direction, shutter_time(self.time0, self.time1, sampler)))
     }
-}
\ No newline at end of file
+}
+
+// image circle as tall as the image, covering `fov` degrees edge to edge
+#[derive(Copy, Clone)]
+pub struct FisheyeCamera {
+    pub origin: Point3,
+    pub uvw: [Vec3; 3],
+    pub model: FisheyeModel,
+    pub fov: f64,
+    pub aspect_ratio: f64,
+    pub time0: f64,
+    pub time1: f64
+}
+
+impl FisheyeCamera {
+    pub fn new(view: &View, model: FisheyeModel, fov: f64, aspect_ratio: f64) -> FisheyeCamera {
+        FisheyeCamera {
+            origin: view.origin, uvw: view_basis(view.origin, view.lookat, view.vup), model, fov: fov.clamp(1.0, 360.0), aspect_ratio, time0: view.time0, time1: view.time1
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let x = (2.0 * s - 1.0) * self.aspect_ratio;
+        let y = 2.0 * t - 1.0;
+        let r = (x * x + y * y).sqrt();
+
+        if r > 1.0 {
+            return None
+        }
+
+        let theta_max = self.fov.to_radians() / 2.0;
+        let theta = match self.model {
+            FisheyeModel::Equidistant => r * theta_max,
+            FisheyeModel::Equisolid => 2.0 * (r * (theta_max / 2.0).sin()).asin()
+        };
+        let phi = y.atan2(x);
+        let [u, v, w] = self.uvw;
+
+        let direction = theta.sin() * (phi.cos() * u + phi.sin() * v) - theta.cos() * w;
+
+        Some(Ray::new(self.origin, direction, shutter_time(self.time0, self.time1, sampler)))
+    }
+}
+
+// moment within the shutter interval the ray is sent out at, for motion blur
+fn shutter_time(time0: f64, time1: f64, sampler: &mut dyn Sampler) -> f64 {
+    time0 + (time1 - time0) * sampler.next_1d()
+}
+
+// u points right, v up and w backwards, away from `lookat`
+fn view_basis(origin: Point3, lookat: Point3, vup: Vec3) -> [Vec3; 3] {
+    let w = unit_vector(&(origin - lookat));
+    let u = unit_vector(&cross_product(&vup, &w));
+    let v = cross_product(&w, &u);
+
+    [u, v, w]
+}
@@ -1,4 +1,6 @@
-use crate::util::clampf;
+use clap::ValueEnum;
+
+use crate::{util::clampf, vec3::Vec3};
 
 pub type Color = (f64, f64, f64);
 
@@ -41,29 +43,80 @@ pub fn write_color_to_list(vector: &mut Vec<String>, pixel_color: Color, samples
     vector.push(cstr);
 }
 
-pub fn apply_samples(list: &mut Vec<f64>, samples_per_pixel: u64, height: u32, width: u32) -> Vec<u8> {
+// rec. 709 weights, how bright a linear color looks
+pub fn luminance(c: &Vec3) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
 
-    let mut res: Vec<u8> = Vec::with_capacity((height * width * 3).try_into().unwrap());
+// divides the weighted sample sums of every pixel by their total weight, then exposes, tone maps and
+// srgb encodes them to 8 bits per channel
+pub fn apply_samples(list: &[f64], weights: &[f64], height: u32, width: u32, display: &DisplayOptions) -> Vec<u8> {
 
-    for c in list.chunks(3) {
-        let mut r = c[0];
-        let mut g = c[1];
-        let mut b = c[2];
+    let mut res: Vec<u8> = Vec::with_capacity((height * width * 3).try_into().unwrap());
 
-        let scale: f64 = 1.0 / samples_per_pixel as f64;
+    let exposure = 2f64.powf(display.exposure);
 
-        r *= scale;
-        g *= scale;
-        b *= scale;
+    for (c, w) in list.chunks(3).zip(weights) {
+        let scale: f64 = if *w == 0.0 { 0.0 } else { exposure / w };
+        let (r, g, b) = display.tone_map.apply((c[0] * scale, c[1] * scale, c[2] * scale));
 
-        res.push((256.0 * clampf(r, 0.0, 0.999)) as u8);
-        res.push((256.0 * clampf(g, 0.0, 0.999)) as u8);
-        res.push((256.0 * clampf(b, 0.0, 0.999)) as u8);
+        res.push((256.0 * clampf(linear_to_srgb(r), 0.0, 0.999)) as u8);
+        res.push((256.0 * clampf(linear_to_srgb(g), 0.0, 0.999)) as u8);
+        res.push((256.0 * clampf(linear_to_srgb(b), 0.0, 0.999)) as u8);
     }
 
     res
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ToneMap {
+    /// cut everything above 1
+    Clamp,
+    /// x / (1 + x), never quite reaches white
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces
+}
+
+impl ToneMap {
+    pub fn apply(&self, color: Color) -> Color {
+        let curve = |x: f64| -> f64 {
+            let x = x.max(0.0);
+
+            match self {
+                ToneMap::Clamp => x.min(1.0),
+                ToneMap::Reinhard => x / (1.0 + x),
+                ToneMap::Aces => ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+            }
+        };
+
+        (curve(color.0), curve(color.1), curve(color.2))
+    }
+}
+
+// how radiance becomes display values for 8 bit output
+#[derive(Clone, Copy, Debug)]
+pub struct DisplayOptions {
+    /// in stops, every +1 doubles the brightness
+    pub exposure: f64,
+    pub tone_map: ToneMap
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        DisplayOptions { exposure: 0.0, tone_map: ToneMap::Clamp }
+    }
+}
+
+// srgb opto-electronic transfer function, linear [0, 1] in
+pub fn linear_to_srgb(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
 pub fn write_vector_to_stdout(vector: &mut Vec<String>) {
     print!("{}", vector.join("\n"))
 }
\ No newline at end of file
@@ -0,0 +1,292 @@
+use std::{f64::consts::PI, fs, path::Path};
+
+use crate::{vec3::{Vec3, unit_vector}, color::luminance, sampler::Sampler, error::{self, Error}};
+
+// radiance arriving from infinitely far away, seen by every ray that leaves the scene
+pub trait Environment: Send + Sync {
+    fn value(&self, direction: &Vec3) -> Vec3;
+
+    // environments that can be importance sampled are treated like one more light by the integrator
+    fn sampled(&self) -> bool {
+        false
+    }
+
+    // solid angle density of `random` picking `direction`, only needed when `sampled`
+    fn pdf_value(&self, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn random(&self, _sampler: &mut dyn Sampler) -> Vec3 {
+        Vec3::new(0.0, 1.0, 0.0)
+    }
+}
+
+pub struct ConstantEnvironment {
+    pub color: Vec3
+}
+
+impl ConstantEnvironment {
+    pub fn new(color: Vec3) -> ConstantEnvironment { ConstantEnvironment { color } }
+}
+
+impl Environment for ConstantEnvironment {
+    fn value(&self, _direction: &Vec3) -> Vec3 {
+        self.color
+    }
+}
+
+// blends from `bottom` straight down to `top` straight up
+pub struct GradientEnvironment {
+    pub bottom: Vec3,
+    pub top: Vec3
+}
+
+impl GradientEnvironment {
+    pub fn new(bottom: Vec3, top: Vec3) -> GradientEnvironment { GradientEnvironment { bottom, top } }
+
+    // the white to blue sky every scene used before environments were configurable
+    pub fn sky() -> GradientEnvironment {
+        GradientEnvironment::new(Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Environment for GradientEnvironment {
+    fn value(&self, direction: &Vec3) -> Vec3 {
+        let unit_direction = unit_vector(direction);
+        let t = 0.5 * (unit_direction.y + 1.0);
+
+        (1.0 - t) * self.bottom + t * self.top
+    }
+}
+
+// latitude-longitude map, +y is the top row and -z the center of the image. Directions are importance
+// sampled by texel luminance so small bright spots like the sun don't show up as fireflies
+pub struct ImageEnvironment {
+    width: usize,
+    height: usize,
+    texels: Vec<Vec3>,
+    rows: Distribution1D,
+    columns: Vec<Distribution1D>
+}
+
+impl ImageEnvironment {
+    pub fn new(width: usize, height: usize, texels: Vec<Vec3>) -> error::Result<ImageEnvironment> {
+        if width == 0 || height == 0 || texels.len() != width * height {
+            return Err(Error::ImageSize { width, height, texels: texels.len() })
+        }
+
+        // rows near the poles cover less solid angle, weigh them down by sin(theta)
+        let columns: Vec<Distribution1D> = (0..height).map(|y| {
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            Distribution1D::new(texels[y * width..(y + 1) * width].iter().map(|t| luminance(t) * sin_theta).collect())
+        }).collect();
+
+        let rows = Distribution1D::new(columns.iter().map(|c| c.total).collect());
+
+        Ok(ImageEnvironment { width, height, texels, rows, columns })
+    }
+
+    // each texel is multiplied by `strength`
+    pub fn load(path: &Path, strength: f64) -> error::Result<ImageEnvironment> {
+        let bytes = fs::read(path).map_err(|e| Error::io(path, e))?;
+        let (width, height, texels) = read_hdr(&bytes).map_err(|e| Error::invalid(path, e))?;
+
+        ImageEnvironment::new(width, height, texels.into_iter().map(|t| strength * t).collect()).map_err(|e| Error::invalid(path, e))
+    }
+
+    fn texel_of(&self, direction: &Vec3) -> (usize, usize, f64) {
+        let d = unit_vector(direction);
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        let phi = d.x.atan2(-d.z);
+
+        let u = 0.5 + phi / (2.0 * PI);
+        let v = theta / PI;
+
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+
+        (x, y, theta.sin())
+    }
+}
+
+impl Environment for ImageEnvironment {
+    fn value(&self, direction: &Vec3) -> Vec3 {
+        let (x, y, _) = self.texel_of(direction);
+
+        self.texels[y * self.width + x]
+    }
+
+    // an all black image has nothing worth sampling
+    fn sampled(&self) -> bool {
+        self.rows.total > 0.0
+    }
+
+    fn random(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let (r1, r2) = sampler.next_2d();
+        let y = self.rows.sample(r1);
+        let x = self.columns[y].sample(r2);
+
+        // uniform within the texel
+        let (du, dv) = sampler.next_2d();
+        let u = (x as f64 + du) / self.width as f64;
+        let v = (y as f64 + dv) / self.height as f64;
+
+        let theta = v * PI;
+        let phi = (u - 0.5) * 2.0 * PI;
+
+        Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+    }
+
+    fn pdf_value(&self, direction: &Vec3) -> f64 {
+        let (x, y, sin_theta) = self.texel_of(direction);
+
+        if self.rows.total <= 0.0 || sin_theta <= 0.0 {
+            return 0.0
+        }
+
+        // density over the image, then from image area to solid angle
+        let image_pdf = self.rows.pdf(y) * self.columns[y].pdf(x) * (self.width * self.height) as f64;
+
+        image_pdf / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+// piecewise constant distribution over indices, proportional to the weights it was built from
+struct Distribution1D {
+    weights: Vec<f64>,
+    cdf: Vec<f64>,
+    total: f64
+}
+
+impl Distribution1D {
+    fn new(weights: Vec<f64>) -> Distribution1D {
+        let mut cdf = Vec::with_capacity(weights.len());
+        let mut total = 0.0;
+
+        for w in weights.iter() {
+            total += w.max(0.0);
+            cdf.push(total);
+        }
+
+        Distribution1D { weights, cdf, total }
+    }
+
+    fn sample(&self, u: f64) -> usize {
+        let target = u * self.total;
+
+        self.cdf.partition_point(|c| *c <= target).min(self.cdf.len() - 1)
+    }
+
+    // probability of `sample` returning `i`
+    fn pdf(&self, i: usize) -> f64 {
+        if self.total <= 0.0 {
+            return 0.0
+        }
+
+        self.weights[i].max(0.0) / self.total
+    }
+}
+
+// radiance rgbe, both flat and run length encoded scanlines, only the usual -Y h +X w orientation
+fn read_hdr(bytes: &[u8]) -> Result<(usize, usize, Vec<Vec3>), String> {
+    let mut pos = 0;
+    let mut next_line = || -> Result<&str, String> {
+        let end = bytes[pos..].iter().position(|b| *b == b'\n').ok_or("unexpected end of header")?;
+        let line = std::str::from_utf8(&bytes[pos..pos + end]).map_err(|_| "header is not text")?;
+        pos += end + 1;
+        Ok(line.trim_end_matches('\r'))
+    };
+
+    if !next_line()?.starts_with("#?") {
+        return Err("missing #? signature, not a radiance file".to_string())
+    }
+
+    loop {
+        let line = next_line()?;
+
+        if line.is_empty() {
+            break
+        }
+
+        match line.strip_prefix("FORMAT=") {
+            Some(format) if format != "32-bit_rle_rgbe" => return Err(format!("unsupported format {}", format)),
+            _ => ()
+        }
+    }
+
+    let resolution: Vec<&str> = next_line()?.split_whitespace().collect();
+    let (height, width) = match resolution[..] {
+        ["-Y", h, "+X", w] => (
+            h.parse::<usize>().map_err(|_| "bad image height")?,
+            w.parse::<usize>().map_err(|_| "bad image width")?
+        ),
+        _ => return Err(format!("unsupported resolution line \"{}\"", resolution.join(" ")))
+    };
+
+    if width == 0 || height == 0 {
+        return Err(format!("image is {}x{}, there is nothing to sample", width, height))
+    }
+
+    // 4 bytes a pixel, so 4 * width can't overflow further down either
+    let pixels = width.checked_mul(height).filter(|n| n.checked_mul(4).is_some()).ok_or("image is too large")?;
+
+    let mut data = &bytes[pos..];
+    let mut rgbe = vec![[0u8; 4]; pixels];
+
+    for row in rgbe.chunks_mut(width) {
+        let rle = (8..0x8000).contains(&width) && data.len() >= 4 && data[0] == 2 && data[1] == 2 && data[2] & 0x80 == 0;
+
+        if !rle {
+            let flat = data.get(..4 * width).ok_or("pixel data is truncated")?;
+            for (px, src) in row.iter_mut().zip(flat.chunks(4)) {
+                px.copy_from_slice(src);
+            }
+            data = &data[4 * width..];
+            continue
+        }
+
+        if ((data[2] as usize) << 8 | data[3] as usize) != width {
+            return Err("scanline width doesn't match the image".to_string())
+        }
+        data = &data[4..];
+
+        // each channel is stored separately as runs and literals
+        for channel in 0..4 {
+            let mut x = 0;
+
+            while x < width {
+                let (&count, rest) = data.split_first().ok_or("pixel data is truncated")?;
+
+                if count > 128 {
+                    let run = (count - 128) as usize;
+                    let value = *rest.first().ok_or("pixel data is truncated")?;
+                    if x + run > width {
+                        return Err("run goes past the end of a scanline".to_string())
+                    }
+                    row[x..x + run].iter_mut().for_each(|px| px[channel] = value);
+                    x += run;
+                    data = &rest[1..];
+                } else {
+                    let run = count as usize;
+                    if run == 0 || x + run > width || rest.len() < run {
+                        return Err("bad literal run in scanline".to_string())
+                    }
+                    row[x..x + run].iter_mut().zip(&rest[..run]).for_each(|(px, v)| px[channel] = *v);
+                    x += run;
+                    data = &rest[run..];
+                }
+            }
+        }
+    }
+
+    let texels = rgbe.iter().map(|[r, g, b, e]| {
+        if *e == 0 {
+            return Vec3::new(0.0, 0.0, 0.0)
+        }
+
+        let f = 2f64.powi(*e as i32 - 136);
+        Vec3::new(*r as f64 * f, *g as f64 * f, *b as f64 * f)
+    }).collect();
+
+    Ok((width, height, texels))
+}
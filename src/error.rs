@@ -0,0 +1,58 @@
+use std::{fmt, io, path::PathBuf};
+
+// everything loading a scene, building it and writing the result can fail with
+#[derive(Debug)]
+pub enum Error {
+    /// a file couldn't be opened, read or written
+    Io { path: PathBuf, source: io::Error },
+    /// a file was read fine but its contents make no sense (bad obj, png, hdr, toml, render state, ...)
+    Invalid { path: PathBuf, message: String },
+    /// the scene description refers to something that doesn't exist or can't be built
+    Scene(String),
+    /// there is nothing to build a bvh from
+    EmptyScene,
+    /// a primitive without a bounding box can't go in a bvh
+    Unbounded,
+    /// a transform that can't be inverted, e.g. a scale of 0
+    SingularTransform,
+    /// an image's texels don't fill its width and height, or it has no texels at all
+    ImageSize { width: usize, height: usize, texels: usize },
+    /// the render couldn't be started, e.g. no thread pool or ctrl-c handler
+    Setup(String)
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Error {
+        Error::Io { path: path.into(), source }
+    }
+
+    pub fn invalid(path: impl Into<PathBuf>, message: impl ToString) -> Error {
+        Error::Invalid { path: path.into(), message: message.to_string() }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Invalid { path, message } => write!(f, "{}: {}", path.display(), message),
+            Error::Scene(message) => write!(f, "{}", message),
+            Error::EmptyScene => write!(f, "the scene is empty, there is nothing to render"),
+            Error::Unbounded => write!(f, "an object without a bounding box can't be put in a bvh"),
+            Error::SingularTransform => write!(f, "transform is not invertible, is one of the scales 0?"),
+            Error::ImageSize { width, height, texels } => write!(f, "a {}x{} image can't be made of {} texels", width, height, texels),
+            Error::Setup(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
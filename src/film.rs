@@ -0,0 +1,270 @@
+use std::{fs::{self, File}, io::{self, BufWriter, Write}, path::Path};
+
+use crate::{color::luminance, vec3::Vec3, filter::Filter, tiles::Tile, error::{Error, Result}};
+
+const STATE_MAGIC: &[u8; 8] = b"RTFILM03";
+
+// running sums of every sample taken so far, rows from the top of the image down. The color is the
+// filter weighted sum of every sample close enough to the pixel, the sample count, luminance and
+// squared luminance only cover the samples taken inside the pixel itself. Each pixel keeps its own
+// sample count so a render stopped halfway through a pass still averages correctly
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    /// filter weighted rgb sums
+    pub sum: Vec<f64>,
+    pub weights: Vec<f64>,
+    /// sums of the sample luminance and its square, for the variance
+    pub luminance: Vec<f64>,
+    pub squares: Vec<f64>,
+    pub samples: Vec<u64>
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        let pixels = (width * height) as usize;
+
+        Film {
+            width,
+            height,
+            sum: vec![0.0; 3 * pixels],
+            weights: vec![0.0; pixels],
+            luminance: vec![0.0; pixels],
+            squares: vec![0.0; pixels],
+            samples: vec![0; pixels]
+        }
+    }
+
+    // empty buffer for the samples of `tile`, it reaches past the tile as far as `filter` spreads them
+    pub fn tile(&self, tile: &Tile, filter: Filter) -> FilmTile {
+        // the cast saturates, a radius wider than the image just makes the tile cover all of it
+        let margin = (filter.radius + 0.5).ceil() as u32;
+
+        let x0 = tile.x0.saturating_sub(margin);
+        let y0 = tile.y0.saturating_sub(margin);
+        let x1 = tile.x1.saturating_add(margin).min(self.width);
+        let y1 = tile.y1.saturating_add(margin).min(self.height);
+
+        FilmTile { x0, y0, film: Film::new(x1 - x0, y1 - y0), filter }
+    }
+
+    // adds everything `tile` collected, neighbouring tiles overlap so this has to happen one at a time
+    pub fn merge(&mut self, tile: &FilmTile) {
+        let width = tile.film.width as usize;
+
+        for y in 0..tile.film.height as usize {
+            let first = (tile.y0 as usize + y) * self.width as usize + tile.x0 as usize;
+            let row = y * width..(y + 1) * width;
+
+            let add = |acc: &mut [f64], values: &[f64]| acc.iter_mut().zip(values).for_each(|(a, v)| *a += v);
+
+            add(&mut self.sum[3 * first..3 * (first + width)], &tile.film.sum[3 * row.start..3 * row.end]);
+            add(&mut self.weights[first..first + width], &tile.film.weights[row.clone()]);
+            add(&mut self.luminance[first..first + width], &tile.film.luminance[row.clone()]);
+            add(&mut self.squares[first..first + width], &tile.film.squares[row.clone()]);
+
+            for (acc, n) in self.samples[first..first + width].iter_mut().zip(&tile.film.samples[row]) {
+                *acc += n;
+            }
+        }
+    }
+
+    // filtered rgb of every pixel, black where nothing was sampled yet
+    pub fn average(&self) -> Vec<f64> {
+        self.sum.chunks(3).zip(&self.weights).flat_map(|(c, w)| {
+            let scale = if *w == 0.0 { 0.0 } else { 1.0 / w };
+            [c[0] * scale, c[1] * scale, c[2] * scale]
+        }).collect()
+    }
+
+    pub fn image(&self) -> Image {
+        Image { width: self.width, height: self.height, rgb: self.average() }
+    }
+
+    // standard error of the mean luminance of pixel `i` relative to that mean, infinite until there
+    // are at least two samples to estimate the variance from. Dark pixels are measured against a floor
+    // so the last bits of noise in the shadows don't eat up the whole sample budget
+    pub fn relative_error(&self, i: usize) -> f64 {
+        let n = self.samples[i] as f64;
+
+        if n < 2.0 {
+            return f64::INFINITY
+        }
+
+        let mean = self.luminance[i] / n;
+        let variance = ((self.squares[i] - n * mean * mean) / (n - 1.0)).max(0.0);
+
+        (variance / n).sqrt() / mean.max(0.01)
+    }
+
+    // raw dump of the sums and sample counts so a render can be picked up again later, little endian
+    pub fn save(&self, path: &Path) -> Result<()> {
+        self.write_state(path).map_err(|e| Error::io(path, e))
+    }
+
+    fn write_state(&self, path: &Path) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+
+        w.write_all(STATE_MAGIC)?;
+        w.write_all(&self.width.to_le_bytes())?;
+        w.write_all(&self.height.to_le_bytes())?;
+
+        for i in 0..self.samples.len() {
+            for v in [self.sum[3 * i], self.sum[3 * i + 1], self.sum[3 * i + 2], self.weights[i], self.luminance[i], self.squares[i]] {
+                w.write_all(&v.to_le_bytes())?;
+            }
+            w.write_all(&self.samples[i].to_le_bytes())?;
+        }
+
+        w.flush()
+    }
+
+    pub fn load(path: &Path) -> Result<Film> {
+        let bytes = fs::read(path).map_err(|e| Error::io(path, e))?;
+        let invalid = |msg: &str| Error::invalid(path, msg);
+
+        if bytes.len() < 16 || &bytes[..8] != STATE_MAGIC {
+            return Err(invalid("not a render state file"))
+        }
+
+        let width = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        let height = u32::from_le_bytes(bytes[12..16].try_into().unwrap());
+        let pixels = &bytes[16..];
+
+        if pixels.len() != width as usize * height as usize * 56 {
+            return Err(invalid("render state file is truncated"))
+        }
+
+        let mut film = Film::new(width, height);
+
+        for (i, px) in pixels.chunks(56).enumerate() {
+            let value = |k: usize| f64::from_le_bytes(px[8 * k..8 * k + 8].try_into().unwrap());
+
+            film.sum[3 * i..3 * i + 3].copy_from_slice(&[value(0), value(1), value(2)]);
+            film.weights[i] = value(3);
+            film.luminance[i] = value(4);
+            film.squares[i] = value(5);
+            film.samples[i] = u64::from_le_bytes(px[48..56].try_into().unwrap());
+        }
+
+        Ok(film)
+    }
+}
+
+// finished render, linear and unclamped rgb with rows from the top of the image down
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgb: Vec<f64>
+}
+
+impl Image {
+    pub fn pixel(&self, x: u32, y: u32) -> Vec3 {
+        let i = 3 * (y * self.width + x) as usize;
+
+        Vec3::new(self.rgb[i], self.rgb[i + 1], self.rgb[i + 2])
+    }
+}
+
+// the samples of one tile on their way into the film, `film` covers the image from `x0`, `y0` on
+pub struct FilmTile {
+    pub x0: u32,
+    pub y0: u32,
+    pub film: Film,
+    filter: Filter
+}
+
+impl FilmTile {
+    // a sample of pixel `px`, `py` (from the top left of the image) taken `dx`, `dy` into it. It is
+    // counted for that pixel and weighted into every pixel the filter reaches
+    pub fn add_sample(&mut self, px: u32, py: u32, dx: f64, dy: f64, color: Vec3) {
+        let film = &mut self.film;
+        let r = self.filter.radius;
+        let (x, y) = (px as f64 + dx, py as f64 + dy);
+
+        // pixel centers are at .5, the filter reaches those within r on either side
+        let reach = |p: f64, origin: u32, size: u32| {
+            let first = ((p - 0.5 - r).ceil() as i64).saturating_sub(origin as i64).max(0);
+            let last = ((p - 0.5 + r).floor() as i64).saturating_sub(origin as i64).min(size as i64 - 1);
+            first..=last
+        };
+
+        for ty in reach(y, self.y0, film.height) {
+            for tx in reach(x, self.x0, film.width) {
+                let weight = self.filter.weight(x - (self.x0 as f64 + tx as f64 + 0.5), y - (self.y0 as f64 + ty as f64 + 0.5));
+
+                if weight == 0.0 {
+                    continue
+                }
+
+                let i = ty as usize * film.width as usize + tx as usize;
+                film.sum[3 * i] += weight * color.x;
+                film.sum[3 * i + 1] += weight * color.y;
+                film.sum[3 * i + 2] += weight * color.z;
+                film.weights[i] += weight;
+            }
+        }
+
+        // from the integer pixel, the position can land on the next pixel's edge when dy is 1
+        let i = (py - self.y0) as usize * film.width as usize + (px - self.x0) as usize;
+        let l = luminance(&color);
+
+        film.luminance[i] += l;
+        film.squares[i] += l * l;
+        film.samples[i] += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterKind;
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut film = Film::new(5, 3);
+        let tile = Tile { x0: 0, y0: 0, x1: 5, y1: 3 };
+        let mut film_tile = film.tile(&tile, Filter::new(FilterKind::Gaussian, 1.5));
+
+        film_tile.add_sample(1, 2, 0.25, 0.75, Vec3::new(0.5, 2.0, 0.125));
+        film_tile.add_sample(4, 0, 0.5, 0.5, Vec3::new(3.0, 0.0, 1.0));
+        film_tile.add_sample(4, 0, 0.9, 0.1, Vec3::new(0.1, 0.2, 0.3));
+        film.merge(&film_tile);
+
+        let path = std::env::temp_dir().join(format!("raytracer-film-test-{}.state", std::process::id()));
+        film.save(&path).unwrap();
+        let loaded = Film::load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!((loaded.width, loaded.height), (film.width, film.height));
+        assert_eq!(loaded.sum, film.sum);
+        assert_eq!(loaded.weights, film.weights);
+        assert_eq!(loaded.luminance, film.luminance);
+        assert_eq!(loaded.squares, film.squares);
+        assert_eq!(loaded.samples, film.samples);
+    }
+
+    #[test]
+    fn sample_on_the_bottom_edge_counts_for_its_own_pixel() {
+        let film = Film::new(4, 4);
+        let mut film_tile = film.tile(&Tile { x0: 0, y0: 2, x1: 4, y1: 4 }, Filter::new(FilterKind::Box, 0.5));
+
+        // the renderer flips the sampler's y, so an offset of exactly 1 does happen
+        film_tile.add_sample(2, 3, 0.5, 1.0, Vec3::new(1.0, 1.0, 1.0));
+
+        let i = (3 - film_tile.y0) as usize * film_tile.film.width as usize + 2;
+        assert_eq!(film_tile.film.samples[i], 1);
+        assert_eq!(film_tile.film.samples.iter().sum::<u64>(), 1);
+    }
+
+    #[test]
+    fn load_rejects_other_files() {
+        let path = std::env::temp_dir().join(format!("raytracer-film-test-bad-{}.state", std::process::id()));
+        fs::write(&path, b"not a render state").unwrap();
+        let loaded = Film::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(loaded, Err(Error::Invalid { .. })));
+    }
+}
@@ -0,0 +1,86 @@
+use std::f64::consts::PI;
+
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FilterKind {
+    /// every sample counts fully toward the pixel it landed in and nothing else
+    Box,
+    /// weight falls off linearly toward the radius
+    Tent,
+    /// gaussian with a standard deviation of a third of the radius, shifted to reach 0 at the radius
+    Gaussian,
+    /// Mitchell-Netravali with B = C = 1/3, sharp with very little ringing
+    Mitchell,
+    /// four term Blackman-Harris window, a smoother and slightly sharper alternative to the gaussian
+    BlackmanHarris
+}
+
+impl FilterKind {
+    // the radius (in pixels) the filter is usually used with
+    pub fn default_radius(&self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell | FilterKind::BlackmanHarris => 2.0
+        }
+    }
+}
+
+// separable pixel reconstruction filter, every sample is added to the pixels whose centers are within
+// `radius` of it weighted by `weight`
+#[derive(Clone, Copy, Debug)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f64
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, radius: f64) -> Filter {
+        Filter { kind, radius }
+    }
+
+    // weight of a sample `dx`, `dy` pixels away from a pixel center. Mitchell goes slightly negative
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, x: f64) -> f64 {
+        let r = self.radius;
+
+        // half open so a box filter counts a sample on a pixel edge exactly once
+        if x < -r || x >= r {
+            return 0.0
+        }
+
+        let x = x.abs();
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => r - x,
+            FilterKind::Gaussian => {
+                let sigma = r / 3.0;
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+
+                gaussian(x) - gaussian(r)
+            },
+            FilterKind::Mitchell => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                // the cubic is defined over [0, 2]
+                let x = 2.0 * x / r;
+
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x.powi(3) + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+                } else {
+                    ((-b - 6.0 * c) * x.powi(3) + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+                }
+            },
+            FilterKind::BlackmanHarris => {
+                let t = 2.0 * PI * (x + r) / (2.0 * r);
+
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+}
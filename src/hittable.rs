@@ -1,6 +1,6 @@
 use std::{sync::Arc};
 
-use crate::{vec3::*, ray::Ray, material::Scatter, aabb::AABB};
+use crate::{vec3::*, ray::Ray, material::Scatter, aabb::AABB, sampler::Sampler};
 
 pub struct HitRecord {
     pub p: Point3,
@@ -27,6 +27,21 @@ impl HitRecord {
 pub trait Hit: Send + Sync {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB>;
+
+    // any hit in [t_min, t_max] at all, for shadow rays that don't care which object is in the way
+    // or need a HitRecord, implementations should stop at the first intersection they find
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.hit(r, t_min, t_max).is_some()
+    }
+
+    // solid angle density of `random` picking `direction` from `origin`, only lights need these two
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn random(&self, _origin: &Point3, _sampler: &mut dyn Sampler) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
 }
 
 pub type World = Vec<Box<dyn Hit>>;
@@ -46,6 +61,10 @@ impl Hit for World {
         tmp_rec
     }
 
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.iter().any(|object| object.occluded(r, t_min, t_max))
+    }
+
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
         match self.first() {
             Some(first) =>
@@ -62,4 +81,21 @@ impl Hit for World {
             _ => None
         }
     }
+
+    // a list of lights is sampled by picking one of them uniformly
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.is_empty() {
+            return 0.0
+        }
+
+        let weight = 1.0 / self.len() as f64;
+
+        self.iter().map(|object| weight * object.pdf_value(origin, direction)).sum()
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        let i = ((sampler.next_1d() * self.len() as f64) as usize).min(self.len() - 1);
+
+        self[i].random(origin, sampler)
+    }
 }
\ No newline at end of file
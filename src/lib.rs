@@ -10,6 +10,19 @@ pub mod camera;
 pub mod material;
 pub mod aabb;
 pub mod bvh;
+pub mod linear_bvh;
 pub mod texture;
 pub mod obj;
-pub mod triangle;
\ No newline at end of file
+pub mod triangle;
+pub mod onb;
+pub mod transform;
+pub mod environment;
+pub mod output;
+pub mod film;
+pub mod tiles;
+pub mod random;
+pub mod filter;
+pub mod sampler;
+pub mod renderer;
+pub mod scene;
+pub mod error;
\ No newline at end of file
@@ -0,0 +1,157 @@
+use crate::ray::Ray;
+use crate::hittable::{Hit, HitRecord, World};
+use crate::aabb::AABB;
+use crate::vec3::Vec3;
+use crate::bvh::{BVHOptions, BuildPrim, partition, bounds};
+use crate::error::Result;
+
+// traversal keeps a fixed size stack, so the build turns anything this deep into a leaf
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Copy)]
+struct LinearNode {
+    bbox: AABB,
+    // leaves: index of the first primitive, interior nodes: index of the second child,
+    // the first child always directly follows its parent
+    offset: u32,
+    // number of primitives, 0 for interior nodes
+    count: u32,
+    // axis the node was split on, decides which child is visited first
+    axis: u8
+}
+
+// Same bvh as `BVH`, but stored as one depth first array of nodes with the primitives reordered so every
+// leaf covers a contiguous range, traversal is a loop over indices instead of chasing boxed children
+pub struct LinearBVH {
+    nodes: Vec<LinearNode>,
+    primitives: Vec<Box<dyn Hit>>
+}
+
+impl LinearBVH {
+    pub fn new(hitable: World, time0: f64, time1: f64, options: &BVHOptions) -> Result<Self> {
+        let mut prims = BuildPrim::from_world(hitable, time0, time1)?;
+        let mut nodes = Vec::with_capacity(2 * prims.len());
+
+        LinearBVH::build(&mut prims, 0, 0, &mut nodes, options);
+
+        Ok(LinearBVH {
+            nodes,
+            primitives: prims.into_iter().map(|p| p.object).collect()
+        })
+    }
+
+    // partition only ever reorders within the slice it's given, so once the whole tree is built the
+    // primitives are already laid out in leaf order
+    fn build(prims: &mut [BuildPrim], start: usize, depth: usize, nodes: &mut Vec<LinearNode>, options: &BVHOptions) -> usize {
+        let bbox = bounds(prims);
+        let index = nodes.len();
+
+        nodes.push(LinearNode { bbox, offset: start as u32, count: prims.len() as u32, axis: 0 });
+
+        if depth + 1 >= MAX_DEPTH {
+            return index
+        }
+
+        if let Some((mid, axis)) = partition(prims, options) {
+            let (left, right) = prims.split_at_mut(mid);
+
+            LinearBVH::build(left, start, depth + 1, nodes, options);
+            let second = LinearBVH::build(right, start + mid, depth + 1, nodes, options);
+
+            nodes[index] = LinearNode { bbox, offset: second as u32, count: 0, axis };
+        }
+
+        index
+    }
+}
+
+impl Hit for LinearBVH {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let inv_dir = Vec3::new(1.0 / r.direction.x, 1.0 / r.direction.y, 1.0 / r.direction.z);
+        let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+
+        let mut stack = [0u32; MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut current = 0;
+
+        let mut closest = t_max;
+        let mut rec = None;
+
+        loop {
+            let node = &self.nodes[current];
+
+            if node.bbox.hit_inv(&r.origin, &inv_dir, t_min, closest) {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+
+                    for object in &self.primitives[first..first + node.count as usize] {
+                        if let Some(hit) = object.hit(r, t_min, closest) {
+                            closest = hit.t;
+                            rec = Some(hit);
+                        }
+                    }
+                } else {
+                    // visit the child on the near side of the split first so `closest` shrinks sooner
+                    if dir_is_neg[node.axis as usize] {
+                        stack[stack_len] = current as u32 + 1;
+                        current = node.offset as usize;
+                    } else {
+                        stack[stack_len] = node.offset;
+                        current += 1;
+                    }
+
+                    stack_len += 1;
+                    continue
+                }
+            }
+
+            if stack_len == 0 {
+                break
+            }
+
+            stack_len -= 1;
+            current = stack[stack_len] as usize;
+        }
+
+        rec
+    }
+
+    // same walk as `hit` but done as soon as anything is found, so child order doesn't matter
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        let inv_dir = Vec3::new(1.0 / r.direction.x, 1.0 / r.direction.y, 1.0 / r.direction.z);
+
+        let mut stack = [0u32; MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut current = 0;
+
+        loop {
+            let node = &self.nodes[current];
+
+            if node.bbox.hit_inv(&r.origin, &inv_dir, t_min, t_max) {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+
+                    if self.primitives[first..first + node.count as usize].iter().any(|object| object.occluded(r, t_min, t_max)) {
+                        return true
+                    }
+                } else {
+                    stack[stack_len] = node.offset;
+                    stack_len += 1;
+                    current += 1;
+                    continue
+                }
+            }
+
+            if stack_len == 0 {
+                return false
+            }
+
+            stack_len -= 1;
+            current = stack[stack_len] as usize;
+        }
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        Some(self.nodes[0].bbox)
+    }
+}
@@ -1,21 +1,23 @@
 #![feature(let_chains)]
-#![allow(unused_doc_comments, non_snake_case, unused_imports, unused_variables)]
 
-use std::{time::Instant, sync::Arc, fs::File, io::BufWriter, path::Path};
+use std::{time::Instant, sync::{Arc, atomic::{AtomicBool, Ordering}}, fs::OpenOptions, path::Path, collections::HashMap};
 
 use raytracer::{
-    vec3::*,
-    color::*,
-    ray::*,
-    hittable::*,
-    sphere::{Sphere, MovingSphere}, camera::{OrthographicCamera}, material::{Lambertian, Metal, Dielectric}, texture::{CheckerTexture, ConstantTexture}, bvh::BVH, obj::{load_obj_and_position, add_obj_to_world}, util::clamp
+    color::{DisplayOptions, ToneMap},
+    hittable::{Hit, World},
+    camera::{Projection, FisheyeModel}, bvh::{BVHOptions, SplitMethod, BVHLayout}, obj::{load_obj_and_position, add_obj_to_world, ObjPlacement}, util::clamp,
+    scene::{Scene, CameraDesc},
+    environment::GradientEnvironment,
+    output::{write_image, write_heatmap},
+    film::Film,
+    filter::{Filter, FilterKind},
+    sampler::SamplerKind,
+    tiles::TileOrder,
+    renderer::{Renderer, RenderSettings},
+    error::{Error, Result}
 };
 
-use rand::Rng;
-use rand::rngs::ThreadRng;
-use indicatif::ProgressBar;
-use rayon::prelude::*;
-use png;
+use indicatif::{ProgressBar, ProgressStyle};
 use clap::Parser;
 
 #[derive(Parser, Debug)]
@@ -35,179 +37,288 @@ pub struct Args {
     #[arg(long, default_value_t = 720, value_name = "height")]
     image_height: u32,
 
-    /// path to output to, should be a png file
+    /// path to output to, .pfm, .hdr and .exr keep the full float range, anything else is a png
     #[arg(short, long, value_name = "output")]
     output_file_path: String,
 
-    /// comma-seperated paths to obj files to render in, only 1 supported for now
-    #[arg(long, value_name = "obj-files")]
-    obj_files: String,
+    /// obj file to put in the demo scene, optionally moved, scaled (one number or x,y,z) and rotated (degrees),
+    /// can be given any number of times
+    #[arg(long, value_name = "path[:tx,ty,tz[:scale[:rx,ry,rz]]]")]
+    obj: Vec<ObjPlacement>,
+
+    /// scene description file (toml), replaces the built in demo scene
+    #[arg(long, value_name = "scene", conflicts_with = "obj")]
+    scene: Option<String>,
 
     /// max-ray bounces
     #[arg(short, default_value_t = 12, value_name = "max-ray-bounces")]
-    ray_bounces: u8
-}
+    ray_bounces: u8,
 
-fn main() {
+    /// how the bvh picks where to split nodes
+    #[arg(long, value_enum, default_value_t = SplitMethod::Sah)]
+    bvh: SplitMethod,
 
-    let args = Args::parse();
+    /// most primitives in a single bvh leaf
+    #[arg(long, default_value_t = 4, value_name = "leaf-size")]
+    bvh_leaf_size: usize,
 
-    rayon::ThreadPoolBuilder::new().num_threads(args.threads.into()).build_global().unwrap();
-    
-    let start = Instant::now();
-    // Program config
-    let MAX_RECURSION_DEPTH: u8 = clamp(args.ray_bounces as usize, 2, 50) as u8;
-    
-    // image configuration
-    let IMAGE_WIDTH: u32 = args.image_width.into();
-    let IMAGE_HEIGHT: u32 = args.image_height.into();
-    let SAMPLES_PER_PIXEL: u64 = args.samples.into();
-    let ASPECT_RATIO: f64 = IMAGE_WIDTH as f64 / IMAGE_HEIGHT as f64;
+    /// memory layout of the bvh, flat is faster to traverse
+    #[arg(long, value_enum, default_value_t = BVHLayout::Flat)]
+    bvh_layout: BVHLayout,
 
-    let world = demo(&args);
+    /// camera projection, overrides the one set in the scene file
+    #[arg(long, value_enum)]
+    projection: Option<Projection>,
 
-    let origin = Point3::new(120., 10.0, 120.);
-    let lookat = Point3::new(0.0, 0.0, 0.0);
-    let vup = Vec3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = 20.0;
-    let aperture = 0.1;
+    /// exposure in stops applied before tone mapping, png output only
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    exposure: f64,
 
-    let camera = OrthographicCamera::new(origin, lookat, vup, 45.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+    /// how radiance above 1 is brought into range for png output
+    #[arg(long, value_enum, default_value_t = ToneMap::Clamp)]
+    tone_map: ToneMap,
 
-    let bar = &Box::new(ProgressBar::new(IMAGE_HEIGHT as u64));
-    bar.tick();
+    /// render in passes of 1, 2, 4, ... samples per pixel and keep the output image up to date in between
+    #[arg(long)]
+    progressive: bool,
 
-    let mut list =
-        (0..IMAGE_HEIGHT).rev().collect::<Vec<u32>>().into_par_iter().flat_map(move |j| {
-            let data = (0..IMAGE_WIDTH).flat_map(|i| {
-                let mut rng = rand::thread_rng();
-                let mut pixel_color = Vec3::new(0.0, 0.0, 0.0);
+    /// fewest seconds between two checkpoint images in progressive mode
+    #[arg(long, default_value_t = 10, value_name = "seconds")]
+    checkpoint_interval: u64,
 
-                for _ in 0..SAMPLES_PER_PIXEL {
-                    let u = (i as f64 + rng.gen::<f64>()) / (IMAGE_WIDTH - 1) as f64;
-                    let v = (j as f64 + rng.gen::<f64>()) / (IMAGE_HEIGHT - 1) as f64;
-                    let r = camera.get_ray(u, v);
-                    pixel_color += ray_color(r, &world, MAX_RECURSION_DEPTH.into(), &mut rng);
-                }
+    /// file the raw accumulated samples are saved to along with every image, for --resume
+    #[arg(long, value_name = "state")]
+    state: Option<String>,
 
-                [pixel_color.x, pixel_color.y, pixel_color.z]
-            }).collect::<Vec<f64>>();
+    /// keep adding samples to a saved state until every pixel has --samples, saves back to the same file unless --state is given
+    #[arg(long, value_name = "state")]
+    resume: Option<String>,
 
-            bar.inc(1);
+    /// width and height of the square tiles the image is split into for the render threads
+    #[arg(long, default_value_t = 32, value_name = "pixels")]
+    tile_size: u32,
 
-            data
-        }).collect::<Vec<f64>>();
+    /// order the tiles are rendered in
+    #[arg(long, value_enum, default_value_t = TileOrder::Spiral)]
+    tile_order: TileOrder,
 
-    let sampled = apply_samples(&mut list, SAMPLES_PER_PIXEL, IMAGE_HEIGHT, IMAGE_WIDTH);
+    /// stop sampling a pixel once the standard error of its mean is below this fraction of it, --samples becomes the most any pixel gets
+    #[arg(long, value_name = "threshold")]
+    adaptive: Option<f64>,
 
-    // PNG setup
-    let output_file_path = Path::new(&args.output_file_path);
-    let file = File::create(output_file_path).unwrap();
-    let ref mut w = BufWriter::new(file);
+    /// samples every pixel gets before --adaptive starts checking whether it has converged
+    #[arg(long, default_value_t = 16, value_name = "samples")]
+    min_samples: u64,
 
-    let mut encoder = png::Encoder::new(w, IMAGE_WIDTH, IMAGE_HEIGHT);
-    encoder.set_color(png::ColorType::Rgb);
-    encoder.set_depth(png::BitDepth::Eight);
-    encoder.set_source_gamma(png::ScaledFloat::from_scaled(IMAGE_HEIGHT / IMAGE_WIDTH * 100000));
-    encoder.set_source_chromaticities(png::SourceChromaticities::new(
-        (0.31270, 0.32900),
-        (0.64000, 0.33000),
-        (0.30000, 0.60000),
-        (0.15000, 0.06000)
-    ));
-    let mut writer = encoder.write_header().unwrap();
+    /// png showing how many samples each pixel got, written along with the output image
+    #[arg(long, value_name = "path")]
+    sample_heatmap: Option<String>,
 
-    writer.write_image_data(&sampled).unwrap();
+    /// seed for every random number the render uses, the same inputs and seed always give the same image
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
 
-    eprintln!("Render Time: {:.2?}", start.elapsed());
+    /// how samples are weighted into the pixels around them
+    #[arg(long, value_enum, default_value_t = FilterKind::Box)]
+    filter: FilterKind,
+
+    /// how far in pixels a sample reaches, defaults to what suits the filter (0.5 for box)
+    #[arg(long, value_name = "pixels", value_parser = parse_filter_radius)]
+    filter_radius: Option<f64>,
+
+    /// where the random numbers for pixel positions, the lens, lights and materials come from
+    #[arg(long, value_enum, default_value_t = SamplerKind::Independent)]
+    sampler: SamplerKind
 }
 
-fn ray_color(ray: Ray, world: &Box<dyn Hit>, depth: u64, rng: &mut ThreadRng) -> Vec3 {
+fn parse_filter_radius(s: &str) -> std::result::Result<f64, String> {
+    let radius: f64 = s.parse().map_err(|e| format!("{}", e))?;
+
+    if !radius.is_finite() || radius <= 0.0 {
+        return Err(format!("{} is not a positive radius", s))
+    }
+
+    Ok(radius)
+}
 
-    if depth <= 0 {
-        return Vec3::new(0.0, 0.0, 0.0)
+fn main() {
+    // bad input (a missing file, a broken scene) ends up here instead of in a panic
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
     }
+}
+
+fn run() -> Result<()> {
+    let args = Args::parse();
 
-    // 0.1e-325
-    if let Some(rec) = world.hit(&ray, 0.001, f64::INFINITY) {
-        if let Some((attenuation, scattered)) = rec.mat.scatter(&ray, &rec) {
-            return attenuation * ray_color(scattered, world, depth - 1, rng)
-        } else {
-            return Vec3::new(0.0, 0.0, 0.0)
+    rayon::ThreadPoolBuilder::new().num_threads(args.threads.into()).build_global()
+        .map_err(|e| Error::Setup(format!("could not start {} render threads: {}", args.threads, e)))?;
+    
+    let start = Instant::now();
+    // Program config
+    let max_depth: u8 = clamp(args.ray_bounces as usize, 2, 50) as u8;
+    
+    // image configuration
+    let image_width: u32 = args.image_width.into();
+    let image_height: u32 = args.image_height.into();
+    let samples_per_pixel: u64 = args.samples.into();
+    let aspect_ratio: f64 = image_width as f64 / image_height as f64;
+
+    let bvh_options = BVHOptions { split: args.bvh, leaf_size: args.bvh_leaf_size.max(1), layout: args.bvh_layout };
+
+    let scene = match &args.scene {
+        Some(path) => Scene::load(Path::new(path), aspect_ratio, args.projection, &bvh_options)?,
+        None => {
+            let camera = CameraDesc {
+                lookfrom: [120.0, 10.0, 120.0],
+                lookat: [0.0, 0.0, 0.0],
+                vup: [0.0, 1.0, 0.0],
+                vfov: 45.0,
+                aperture: 0.1,
+                focus_dist: Some(20.0),
+                time0: 0.0,
+                time1: 1.0,
+                projection: args.projection.unwrap_or(Projection::Perspective),
+                view_height: None,
+                fisheye_model: FisheyeModel::Equidistant,
+                fisheye_fov: 180.0
+            };
+
+            Scene {
+                world: demo(&args, &bvh_options)?,
+                lights: Vec::new(),
+                camera: camera.build(aspect_ratio),
+                environment: Box::new(GradientEnvironment::sky())
+            }
         }
+    };
+
+    // the first ctrl-c finishes the samples in flight and saves what's there, a second one quits right away
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let stop = Arc::clone(&stop);
+        ctrlc::set_handler(move || {
+            if stop.swap(true, Ordering::Relaxed) {
+                std::process::exit(130);
+            }
+
+            eprintln!("stopping, press ctrl-c again to quit without saving");
+        }).map_err(|e| Error::Setup(format!("could not set the ctrl-c handler: {}", e)))?;
+    }
+
+    let display = DisplayOptions { exposure: args.exposure, tone_map: args.tone_map };
+    let output_path = Path::new(&args.output_file_path);
+    let state_path = args.state.as_ref().or(args.resume.as_ref()).map(Path::new);
+    let heatmap_path = args.sample_heatmap.as_ref().map(Path::new);
+
+    let mut film = match &args.resume {
+        Some(path) => {
+            let film = Film::load(Path::new(path))?;
+
+            if film.width != image_width || film.height != image_height {
+                let message = format!("render state is {}x{}, not {}x{}", film.width, film.height, image_width, image_height);
+                return Err(Error::invalid(path, message))
+            }
+
+            film
+        },
+        None => Film::new(image_width, image_height)
+    };
+
+    // a typo in an output path should show up now, not after the whole render. Opening for append
+    // leaves an existing file (like the state being resumed) as it is
+    for path in [Some(output_path), state_path, heatmap_path].into_iter().flatten() {
+        OpenOptions::new().append(true).create(true).open(path).map_err(|e| Error::io(path, e))?;
     }
 
-    let unit_direction = unit_vector(&ray.direction);
-    let t = 0.5 * (unit_direction.y + 1.0);
+    let save = |film: &Film| -> Result<()> {
+        write_image(output_path, film, &display)?;
+
+        if let Some(path) = state_path {
+            film.save(path)?;
+        }
+
+        if let Some(path) = heatmap_path {
+            write_heatmap(path, film)?;
+        }
+
+        Ok(())
+    };
+
+    let settings = RenderSettings {
+        width: image_width,
+        height: image_height,
+        samples_per_pixel,
+        max_depth: max_depth.into(),
+        tile_size: args.tile_size,
+        tile_order: args.tile_order,
+        progressive: args.progressive,
+        adaptive: args.adaptive,
+        min_samples: args.min_samples,
+        seed: args.seed,
+        filter: Filter::new(args.filter, args.filter_radius.unwrap_or(args.filter.default_radius())),
+        sampler: args.sampler
+    };
+
+    let renderer = Renderer::new(scene, settings);
+
+    let bar = &Box::new(ProgressBar::new(renderer.remaining(&film)));
+    bar.set_style(ProgressStyle::with_template("{wide_bar} {pos}/{len} samples, {msg} [{elapsed_precise}]").unwrap());
+    bar.tick();
+
+    let mut last_checkpoint = Instant::now();
+
+    renderer.render_film(&mut film, &stop, |progress| {
+        bar.inc(progress.samples);
+        bar.set_message(format!("tile {}/{}", progress.tiles_done, progress.tiles));
+    }, |film| {
+        let remaining = renderer.remaining(film);
 
-    (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
+        // converged pixels drop out of an adaptive render, so there is less left than the bar expected
+        bar.set_length(bar.position() + remaining);
+
+        if args.progressive && remaining > 0 && last_checkpoint.elapsed().as_secs() >= args.checkpoint_interval {
+            // a failed checkpoint isn't worth throwing the render away for, the final save will try again
+            if let Err(e) = save(film) {
+                eprintln!("could not write checkpoint: {}", e);
+            }
+            last_checkpoint = Instant::now();
+        }
+    });
+
+    bar.finish();
+
+    save(&film)?;
+
+    eprintln!("Render Time: {:.2?}", start.elapsed());
+
+    Ok(())
 }
 
-pub fn demo(args: &Args) -> Box<dyn Hit> {
-    let rng = &mut rand::thread_rng();
+pub fn demo(args: &Args, bvh_options: &BVHOptions) -> Result<Box<dyn Hit>> {
     let mut world = World::new();
 
-    // let ground_mat = CheckerTexture::new(ConstantTexture::new(Vec3::new(0.2, 0.3, 0.1)), ConstantTexture::new(Vec3::new(0.9, 0.9, 0.9)));//Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
-    // let ground = Box::new(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(Lambertian::new(ground_mat))));
-    // world.push(ground);
-
-    // for a in 0..=22 {
-    //     for b in 0..=22 {
-    //         let af = a as f64 - 11.0;
-    //         let bf = b as f64 - 11.0;
-
-    //         let c = rng.gen::<f64>();
-    //         let center = Point3::new(af + 0.9 * rng.gen::<f64>(), 0.2, bf + 0.9 * rng.gen::<f64>());
-
-    //         if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-    //             if c < 0.45 {
-    //                 let albedo = Vec3::random(rng) * Vec3::random(rng);
-    //                 let mat = Arc::new(Lambertian::new(ConstantTexture::new(albedo)));
-    //                 let center2 = center + Vec3::new(0.0, rng.gen_range(0.0..=0.5), 0.0);
-
-    //                 let obj = Box::new(MovingSphere::new(center, center2, 0.2, mat, 0.0, 1.0));
-
-    //                 world.push(obj);
-    //             } else if c < 0.75 {
-    //                 let albedo = Vec3::random_in_range(rng, 0.5..=1.0);
-    //                 let fuzz = rng.gen_range(0.0..=0.5);
-    //                 let mat = Arc::new(Metal::new(albedo, fuzz));
-    //                 let obj = Box::new(Sphere::new(center, 0.2, mat));
-
-    //                 world.push(obj);
-    //             } else {
-    //                 let mat = Arc::new(Dielectric::new(1.5));
-    //                 let obj = Box::new(Sphere::new(center, 0.2, mat));
-
-    //                 world.push(obj);
-    //             }
-    //         }
-    //     }
-    // }
-
-    // let mat1 = Arc::new(Dielectric::new(1.5));
-    // world.push(
-    //     Box::new(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, mat1))
-    // );
-
-    // let mat2 = Arc::new(Lambertian::new(ConstantTexture::new(Vec3::new(0.4, 0.2, 0.1))));
-    // world.push(
-    //     Box::new(Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, mat2))
-    // );
-
-    // let mat3 = Arc::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0));
-    // world.push(
-    //     Box::new(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, mat3))
-    // );
-
-    let obj_paths = args.obj_files.split(",");
-
-    for obj in obj_paths.into_iter() {
-        let obj_path = Path::new(obj);
-        let _obj = load_obj_and_position(obj_path);
-
-        add_obj_to_world(&mut world, _obj, Vec3::new(0., 0., 0.))
+    // the same file listed several times is loaded once and instanced
+    let mut meshes: HashMap<&Path, Arc<dyn Hit>> = HashMap::new();
+
+    for placement in args.obj.iter() {
+        let mesh = match meshes.get(placement.path.as_path()) {
+            Some(mesh) => Arc::clone(mesh),
+            None => {
+                let mesh = load_obj_and_position(&placement.path, bvh_options)?;
+                meshes.insert(&placement.path, Arc::clone(&mesh));
+                mesh
+            }
+        };
+
+        add_obj_to_world(&mut world, &mesh, placement.position())?;
+    }
+
+    // nothing to build a bvh over, the sky alone still renders
+    if world.is_empty() {
+        return Ok(Box::new(world))
     }
 
-    Box::new(BVH::new(world, 0.0, 1.0))
+    bvh_options.build(world, 0.0, 1.0)
 }
\ No newline at end of file
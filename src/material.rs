@@ -1,8 +1,33 @@
-use crate::{ray::Ray, hittable::HitRecord, vec3::{random_in_unit_sphere, Vec3, unit_vector, refract, dot_product, reflect}, texture::Texture};
-use rand::Rng;
+use std::f64::consts::PI;
+
+use crate::{ray::Ray, hittable::HitRecord, vec3::{Vec3, Point3, unit_vector, refract, dot_product, reflect, random_cosine_direction, random_phong_direction}, texture::Texture, onb::Onb, sampler::Sampler};
+
+pub struct ScatterRecord {
+    pub ray: Ray,
+    // bsdf * cos / pdf for the sampled direction, what the path throughput gets multiplied by
+    pub attenuation: Vec3,
+    pub pdf: f64,
+    // perfect mirrors and glass only scatter in one direction, `eval` and `pdf` are always 0 for them
+    pub specular: bool
+}
 
 pub trait Scatter: Sync+Send {
-    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Vec3, Ray)>;
+    fn scatter(&self, r: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord>;
+
+    // value of the bsdf for light arriving along `direction` and leaving along -r.direction, without the cosine term
+    fn eval(&self, _r: &Ray, _rec: &HitRecord, _direction: &Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    // solid angle density `scatter` picks `direction` with
+    fn pdf(&self, _r: &Ray, _rec: &HitRecord, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    // radiance given off by the surface itself, black for everything but lights
+    fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
 }
 
 pub struct Lambertian<T: Texture> {
@@ -18,19 +43,41 @@ impl<T: Texture> Lambertian<T> {
 }
 
 impl<T: Texture> Scatter for Lambertian<T> {
-    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Vec3, Ray)> {
-        let mut scatter_direction = rec.normal + random_in_unit_sphere(&mut rand::thread_rng()).normalized();
+    fn scatter(&self, r: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
+        let uvw = Onb::build_from_w(&rec.normal);
+        let direction = uvw.local(&random_cosine_direction(sampler));
+        let pdf = self.pdf(r, rec, &direction);
 
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
+        if pdf <= 0.0 {
+            return None
         }
 
-        let scattered = Ray::new(rec.p, scatter_direction, r.time);
+        Some(ScatterRecord {
+            ray: Ray::new(rec.p, direction, r.time),
+            // albedo / pi * cos / (cos / pi)
+            attenuation: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf,
+            specular: false
+        })
+    }
 
-        Some((self.albedo.value(rec.u, rec.v, &rec.p), scattered))
+    fn eval(&self, _r: &Ray, rec: &HitRecord, direction: &Vec3) -> Vec3 {
+        if dot_product(&rec.normal, direction) <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0)
+        }
+
+        self.albedo.value(rec.u, rec.v, &rec.p) / PI
+    }
+
+    fn pdf(&self, _r: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        let cosine = dot_product(&rec.normal, &unit_vector(direction));
+
+        if cosine <= 0.0 { 0.0 } else { cosine / PI }
     }
 }
 
+// fuzzy metal is a normalized phong lobe around the mirror direction, so it can be evaluated for
+// light sampling, a fuzz of 0 is a perfect mirror
 pub struct Metal {
     albedo: Vec3,
     fuzz: f64
@@ -39,21 +86,77 @@ pub struct Metal {
 impl Metal {
     pub fn new(albedo: Vec3, fuzz: f64) -> Metal {
         Metal {
-            albedo, fuzz
+            albedo, fuzz: fuzz.min(1.0)
         }
     }
+
+    fn exponent(&self) -> f64 {
+        2.0 / (self.fuzz * self.fuzz) - 2.0
+    }
+
+    fn lobe_pdf(&self, reflected: &Vec3, direction: &Vec3) -> f64 {
+        let cos_alpha = dot_product(reflected, &unit_vector(direction));
+
+        if cos_alpha <= 0.0 {
+            return 0.0
+        }
+
+        let n = self.exponent();
+
+        (n + 1.0) / (2.0 * PI) * cos_alpha.powf(n)
+    }
 }
 
 impl Scatter for Metal {
-    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Vec3, Ray)> {
+    fn scatter(&self, r: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
         let reflected = r.direction.reflect(&rec.normal).normalized();
-        let scattered = Ray::new(rec.p, reflected + self.fuzz * random_in_unit_sphere(&mut rand::thread_rng()), r.time);
 
-        if scattered.direction.dot_product(&rec.normal) > 0.0 {
-            Some((self.albedo, scattered))
-        } else {
-            None
+        if self.fuzz <= 0.0 {
+            return Some(ScatterRecord {
+                ray: Ray::new(rec.p, reflected, r.time),
+                attenuation: self.albedo,
+                pdf: 0.0,
+                specular: true
+            })
+        }
+
+        let uvw = Onb::build_from_w(&reflected);
+        let direction = uvw.local(&random_phong_direction(sampler, self.exponent()));
+
+        // lobe samples that end up under the surface are absorbed
+        if direction.dot_product(&rec.normal) <= 0.0 {
+            return None
         }
+
+        Some(ScatterRecord {
+            ray: Ray::new(rec.p, direction, r.time),
+            attenuation: self.albedo,
+            pdf: self.lobe_pdf(&reflected, &direction),
+            specular: false
+        })
+    }
+
+    fn eval(&self, r: &Ray, rec: &HitRecord, direction: &Vec3) -> Vec3 {
+        let cosine = dot_product(&rec.normal, &unit_vector(direction));
+
+        if self.fuzz <= 0.0 || cosine <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0)
+        }
+
+        let reflected = r.direction.reflect(&rec.normal).normalized();
+
+        // chosen so eval * cos / pdf is exactly the albedo, same as a sampled direction
+        self.albedo * (self.lobe_pdf(&reflected, direction) / cosine)
+    }
+
+    fn pdf(&self, r: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        if self.fuzz <= 0.0 || dot_product(&rec.normal, direction) <= 0.0 {
+            return 0.0
+        }
+
+        let reflected = r.direction.reflect(&rec.normal).normalized();
+
+        self.lobe_pdf(&reflected, direction)
     }
 }
 
@@ -75,7 +178,7 @@ impl Dielectric {
 }
 
 impl Scatter for Dielectric {
-    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Vec3, Ray)> {
+    fn scatter(&self, r: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
         let attenuation = Vec3::new(1.0, 1.0, 1.0);
         let refract_ratio: f64;
 
@@ -92,14 +195,39 @@ impl Scatter for Dielectric {
         let cannot_refract = refract_ratio * sin_theta > 1.0;
         let direction: Vec3;
 
-        if cannot_refract || Dielectric::reflectance(cos_theta, refract_ratio) > rand::thread_rng().gen::<f64>() {
+        if cannot_refract || Dielectric::reflectance(cos_theta, refract_ratio) > sampler.next_1d() {
             direction = reflect(&unit_dir, &rec.normal);
         } else {
             direction = refract(&unit_dir, &rec.normal, refract_ratio);
         }
 
-        Some(
-            (attenuation, Ray::new(rec.p, direction, r.time))
-        )
+        Some(ScatterRecord {
+            ray: Ray::new(rec.p, direction, r.time),
+            attenuation,
+            pdf: 0.0,
+            specular: true
+        })
+    }
+}
+
+pub struct DiffuseLight<T: Texture> {
+    emit: T
+}
+
+impl<T: Texture> DiffuseLight<T> {
+    pub fn new(emit: T) -> DiffuseLight<T> {
+        DiffuseLight {
+            emit
+        }
     }
-}
\ No newline at end of file
+}
+
+impl<T: Texture> Scatter for DiffuseLight<T> {
+    fn scatter(&self, _r: &Ray, _rec: &HitRecord, _sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Vec3 {
+        self.emit.value(u, v, p)
+    }
+}
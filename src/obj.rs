@@ -1,13 +1,29 @@
-use std::{path::Path, sync::Arc};
+use std::{cell::RefCell, fs, io::BufReader, path::{Path, PathBuf}, str::FromStr, sync::Arc};
 
 use tobj;
 
-use crate::{hittable::{Hit, World}, vec3::Vec3, material::{Dielectric, Metal, Scatter, Lambertian}, texture::ConstantTexture, triangle::Triangle, bvh::BVH};
+use crate::{hittable::{Hit, World}, vec3::Vec3, transform::{Matrix4, Transform}, material::{Dielectric, Metal, Scatter, Lambertian}, texture::{ConstantTexture, ImageTexture, WrapMode}, triangle::Triangle, bvh::BVHOptions, error::{Error, Result}};
 
-pub fn load_obj_and_position(path: &Path) -> BVH {
-    let obj = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS);
+// the mesh is shared so it can be placed in the world any number of times with `add_obj_to_world`
+pub fn load_obj_and_position(path: &Path, bvh_options: &BVHOptions) -> Result<Arc<dyn Hit>> {
+    let file = fs::File::open(path).map_err(|e| Error::io(path, e))?;
+    let dir = path.parent().unwrap_or(Path::new("."));
 
-    let (models, mats) = obj.unwrap();
+    // tobj only reports that a material library failed, not which one or why
+    let mtl_error = RefCell::new(None);
+    let (models, mats) = tobj::load_obj_buf(&mut BufReader::new(file), &tobj::GPU_LOAD_OPTIONS, |mtl| {
+        let mtl_path = dir.join(mtl);
+
+        let result = match fs::File::open(&mtl_path) {
+            Ok(f) => tobj::load_mtl_buf(&mut BufReader::new(f)).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string())
+        };
+
+        result.map_err(|cause| {
+            *mtl_error.borrow_mut() = Some(format!("could not load material library {}: {}", mtl_path.display(), cause));
+            tobj::LoadError::OpenFileFailed
+        })
+    }).map_err(|e| Error::invalid(path, e))?;
     let mut world: World = Vec::new();
 
     let default_material = Arc::new(Lambertian::new(ConstantTexture::new(Vec3::new(0.6, 0.6, 0.6))));
@@ -18,15 +34,19 @@ pub fn load_obj_and_position(path: &Path) -> BVH {
                 let mat: Arc<dyn Scatter> = match m.illumination_model {
                     Some(7) => Arc::new(Dielectric::new(m.optical_density as f64)),
                     Some(5) => Arc::new(Metal::new(Vec3::new(m.diffuse[0] as f64, m.diffuse[1] as f64, m.diffuse[2] as f64), (1. / m.shininess) as f64)),
+                    // map_Kd paths are relative to the obj file
+                    _ if !m.diffuse_texture.is_empty() => {
+                        let texture_path = path.parent().unwrap_or(Path::new(".")).join(&m.diffuse_texture);
+                        Arc::new(Lambertian::new(ImageTexture::load(&texture_path, WrapMode::Repeat)?))
+                    },
                     _ => Arc::new(Lambertian::new(ConstantTexture::new(Vec3::new(m.diffuse[0] as f64, m.diffuse[1] as f64, m.diffuse[2] as f64))))
                 };
 
-                mat
-            }).collect()
+                Ok(mat)
+            }).collect::<Result<_>>()?
         },
-        Err(e) => {
-            vec![default_material.clone()]
-        }
+        // a broken mtl would otherwise quietly turn every material grey
+        Err(e) => return Err(Error::invalid(path, mtl_error.into_inner().unwrap_or_else(|| e.to_string())))
     };
 
     for m in models.iter() {
@@ -38,27 +58,172 @@ pub fn load_obj_and_position(path: &Path) -> BVH {
             let v2 = Vec3::new(mesh.positions[f[2] as usize * 3] as f64, mesh.positions[f[2] as usize * 3 + 1] as f64, mesh.positions[f[2] as usize * 3 + 2] as f64);
 
             let mat = match mesh.material_id {
-                Some(id) => Arc::clone(&materials[id]),
+                Some(id) => materials.get(id).map(Arc::clone).ok_or_else(|| Error::invalid(path, format!("model \"{}\" uses material {}, but only {} were loaded", m.name, id, materials.len())))?,
                 None => Arc::clone(&default_material)
             };
 
-            let tri: Triangle;
+            let mut tri: Triangle;
 
+            // GPU_LOAD_OPTIONS gives every vertex a single index, so normals share the position indices
             if mesh.normals.len() > 0 {
-                let normal = Vec3::new(mesh.normals[f[0] as usize * 3] as f64, mesh.normals[f[1] as usize * 3] as f64, mesh.normals[f[2] as usize * 3] as f64);
-                tri = Triangle::new_with_normal(v0, v1, v2, normal, mat);
+                let normal = |i: u32| Vec3::new(mesh.normals[i as usize * 3] as f64, mesh.normals[i as usize * 3 + 1] as f64, mesh.normals[i as usize * 3 + 2] as f64);
+                tri = Triangle::new_with_normals(v0, v1, v2, [normal(f[0]), normal(f[1]), normal(f[2])], mat);
             } else {
                 tri = Triangle::new(v0, v1, v2, Arc::clone(&mat));
             }
 
+            if mesh.texcoords.len() > 0 {
+                let uv = |i: u32| (mesh.texcoords[i as usize * 2] as f64, mesh.texcoords[i as usize * 2 + 1] as f64);
+                tri.uvs = Some([uv(f[0]), uv(f[1]), uv(f[2])]);
+            }
+
             world.push(Box::new(tri));
         }
     }
 
-    BVH::new(world, 0.0, 1.0)
+    // a mesh without faces can't be built into a bvh, say which file it was
+    match bvh_options.build(world, 0.0, 1.0) {
+        Ok(bvh) => Ok(Arc::from(bvh)),
+        Err(Error::EmptyScene) => Err(Error::invalid(path, "no faces in mesh")),
+        Err(e) => Err(e)
+    }
+}
 
+pub fn add_obj_to_world(world: &mut World, obj: &Arc<dyn Hit>, position: Matrix4) -> Result<()> {
+    world.push(Box::new(Transform::new(Arc::clone(obj), position)?));
+
+    Ok(())
+}
+
+// one `--obj` argument, `path[:tx,ty,tz[:scale[:rx,ry,rz]]]`. The scale is either one number or x,y,z,
+// the rotation is in degrees around x, y and z, see Matrix4::from_trs. The numbers are taken off the
+// end, so a path can have colons of its own (C:\models\a.obj)
+#[derive(Debug, Clone)]
+pub struct ObjPlacement {
+    pub path: PathBuf,
+    pub translate: Vec3,
+    pub scale: Vec3,
+    pub rotate: Vec3
+}
+
+impl ObjPlacement {
+    pub fn position(&self) -> Matrix4 {
+        Matrix4::from_trs(self.translate, self.rotate, self.scale)
+    }
 }
 
-pub fn add_obj_to_world(world: &mut World, obj: BVH, _position: Vec3) {
-    world.push(Box::new(obj));
+impl FromStr for ObjPlacement {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let numbers = |part: &str| -> Option<Vec<f64>> {
+            part.split(',').map(|n| n.trim().parse::<f64>().ok()).collect()
+        };
+
+        // everything before the last field that isn't a list of numbers is the path
+        let mut path = s;
+        let mut fields: Vec<(&str, Vec<f64>)> = Vec::new();
+
+        while let Some((rest, field)) = path.rsplit_once(':') {
+            let Some(values) = numbers(field) else {
+                break
+            };
+
+            if fields.len() == 3 {
+                return Err(format!("too many fields in \"{}\", expected path[:tx,ty,tz[:scale[:rx,ry,rz]]]", s))
+            }
+
+            fields.insert(0, (field, values));
+            path = rest;
+        }
+
+        if path.is_empty() {
+            return Err("missing obj path".to_string())
+        }
+
+        let mut fields = fields.into_iter();
+        let triple = |field: Option<(&str, Vec<f64>)>, what: &str, default: Vec3| -> std::result::Result<Vec3, String> {
+            match field {
+                None => Ok(default),
+                Some((_, values)) if values.len() == 3 => Ok(Vec3::new(values[0], values[1], values[2])),
+                Some((part, _)) => Err(format!("{} \"{}\" needs 3 numbers", what, part))
+            }
+        };
+
+        let translate = triple(fields.next(), "translation", Vec3::new(0.0, 0.0, 0.0))?;
+        let scale = match fields.next() {
+            None => Vec3::new(1.0, 1.0, 1.0),
+            Some((_, values)) if values.len() == 1 => Vec3::new(values[0], values[0], values[0]),
+            Some((_, values)) if values.len() == 3 => Vec3::new(values[0], values[1], values[2]),
+            Some((part, _)) => return Err(format!("scale \"{}\" needs 1 or 3 numbers", part))
+        };
+        let rotate = triple(fields.next(), "rotation", Vec3::new(0.0, 0.0, 0.0))?;
+
+        Ok(ObjPlacement { path: PathBuf::from(path), translate, scale, rotate })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> ObjPlacement {
+        s.parse().unwrap_or_else(|e| panic!("{}: {}", s, e))
+    }
+
+    fn assert_vec(v: Vec3, expected: [f64; 3]) {
+        assert_eq!([v.x, v.y, v.z], expected);
+    }
+
+    #[test]
+    fn path_only() {
+        let p = parse("models/dragon.obj");
+
+        assert_eq!(p.path, PathBuf::from("models/dragon.obj"));
+        assert_vec(p.translate, [0.0, 0.0, 0.0]);
+        assert_vec(p.scale, [1.0, 1.0, 1.0]);
+        assert_vec(p.rotate, [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn translation() {
+        let p = parse("a.obj:1,-2.5,3");
+
+        assert_eq!(p.path, PathBuf::from("a.obj"));
+        assert_vec(p.translate, [1.0, -2.5, 3.0]);
+        assert_vec(p.scale, [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn uniform_and_per_axis_scale() {
+        assert_vec(parse("a.obj:0,0,0:20").scale, [20.0, 20.0, 20.0]);
+        assert_vec(parse("a.obj:0,0,0:1,2,3").scale, [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn rotation() {
+        let p = parse("a.obj:0,0,-40:10,30,10:0,45,0");
+
+        assert_vec(p.translate, [0.0, 0.0, -40.0]);
+        assert_vec(p.scale, [10.0, 30.0, 10.0]);
+        assert_vec(p.rotate, [0.0, 45.0, 0.0]);
+    }
+
+    #[test]
+    fn colons_in_the_path() {
+        let p = parse(r"C:\models\a.obj");
+        assert_eq!(p.path, PathBuf::from(r"C:\models\a.obj"));
+
+        let p = parse(r"C:\models\a.obj:1,2,3:2");
+        assert_eq!(p.path, PathBuf::from(r"C:\models\a.obj"));
+        assert_vec(p.translate, [1.0, 2.0, 3.0]);
+        assert_vec(p.scale, [2.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn errors() {
+        for bad in ["", ":1,2,3", "a.obj:1,2", "a.obj:0,0,0:1,2", "a.obj:0,0,0:1:45", "a.obj:0,0,0:1:0,0,0:9"] {
+            assert!(bad.parse::<ObjPlacement>().is_err(), "\"{}\" should not parse", bad);
+        }
+    }
 }
\ No newline at end of file
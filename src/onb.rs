@@ -0,0 +1,27 @@
+use crate::vec3::{Vec3, unit_vector, cross_product};
+
+// orthonormal basis around w, used to turn samples generated around +z into world space
+pub struct Onb {
+    pub axis: [Vec3; 3]
+}
+
+impl Onb {
+    pub fn build_from_w(n: &Vec3) -> Onb {
+        let w = unit_vector(n);
+        let a = if w.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let v = unit_vector(&cross_product(&w, &a));
+        let u = cross_product(&w, &v);
+
+        Onb {
+            axis: [u, v, w]
+        }
+    }
+
+    pub fn u(&self) -> Vec3 { self.axis[0] }
+    pub fn v(&self) -> Vec3 { self.axis[1] }
+    pub fn w(&self) -> Vec3 { self.axis[2] }
+
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        a.x * self.u() + a.y * self.v() + a.z * self.w()
+    }
+}
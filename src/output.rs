@@ -0,0 +1,168 @@
+use std::{fs::File, io::{BufWriter, Write}, path::Path};
+
+use crate::{color::{apply_samples, DisplayOptions}, film::Film, error::{Error, Result}};
+
+// picks the format from the extension of `path`. Float formats get the unclamped average as is,
+// anything that isn't .pfm, .hdr or .exr is put through `display` and written as an 8 bit png
+pub fn write_image(path: &Path, film: &Film, display: &DisplayOptions) -> Result<()> {
+    let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+
+    let file = File::create(path).map_err(|e| Error::io(path, e))?;
+    let mut w = BufWriter::new(file);
+
+    let (width, height) = (film.width, film.height);
+    let average = || film.average().into_iter().map(|c| c as f32).collect::<Vec<f32>>();
+
+    let result = match extension.as_deref() {
+        Some("pfm") => write_pfm(&mut w, width, height, &average()),
+        Some("hdr") => write_hdr(&mut w, width, height, &average()),
+        Some("exr") => write_exr(&mut w, width, height, &average()),
+        _ => write_png(&mut w, film, display)
+    };
+
+    result.and_then(|_| w.flush()).map_err(|e| Error::io(path, e))
+}
+
+// how many samples every pixel got, from black through red and yellow to white at the most sampled one
+pub fn write_heatmap(path: &Path, film: &Film) -> Result<()> {
+    let most = film.samples.iter().copied().max().unwrap_or(0).max(1) as f64;
+
+    let pixels: Vec<u8> = film.samples.iter().flat_map(|n| {
+        let t = 3.0 * *n as f64 / most;
+        [t, t - 1.0, t - 2.0].map(|c| (255.0 * c.clamp(0.0, 1.0)) as u8)
+    }).collect();
+
+    let file = File::create(path).map_err(|e| Error::io(path, e))?;
+    let mut w = BufWriter::new(file);
+
+    encode_png(&mut w, film.width, film.height, &pixels)
+        .and_then(|_| w.flush())
+        .map_err(|e| Error::io(path, e))
+}
+
+fn write_png<W: Write>(w: &mut W, film: &Film, display: &DisplayOptions) -> std::io::Result<()> {
+    let sampled = apply_samples(&film.sum, &film.weights, film.height, film.width, display);
+
+    encode_png(w, film.width, film.height, &sampled)
+}
+
+// 8 bit srgb rgb
+fn encode_png<W: Write>(w: &mut W, width: u32, height: u32, pixels: &[u8]) -> std::io::Result<()> {
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    // the sRGB chunk plus the gAMA and cHRM values the spec asks for alongside it, for older readers
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    encoder.set_source_gamma(png::ScaledFloat::from_scaled(45455));
+    encoder.set_source_chromaticities(png::SourceChromaticities::new(
+        (0.31270, 0.32900),
+        (0.64000, 0.33000),
+        (0.30000, 0.60000),
+        (0.15000, 0.06000)
+    ));
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+
+    Ok(())
+}
+
+// portable float map, little endian (negative scale) with rows from the bottom up
+fn write_pfm<W: Write>(w: &mut W, width: u32, height: u32, rgb: &[f32]) -> std::io::Result<()> {
+    write!(w, "PF\n{} {}\n-1.0\n", width, height)?;
+
+    for row in rgb.chunks(3 * width as usize).rev() {
+        for c in row {
+            w.write_all(&c.to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+// radiance rgbe with flat (not run length encoded) scanlines
+fn write_hdr<W: Write>(w: &mut W, width: u32, height: u32, rgb: &[f32]) -> std::io::Result<()> {
+    write!(w, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width)?;
+
+    for px in rgb.chunks(3) {
+        let (r, g, b) = (px[0].max(0.0), px[1].max(0.0), px[2].max(0.0));
+        let m = r.max(g).max(b);
+
+        if !m.is_finite() || m < 1e-32 {
+            w.write_all(&[0, 0, 0, 0])?;
+            continue
+        }
+
+        // m = f * 2^e with f in [0.5, 1), so every channel fits in 8 bits after scaling by 256 / 2^e.
+        // The exponent byte only goes up to 2^127, anything brighter saturates the mantissas instead
+        let e = (m.log2().floor() as i32 + 1).min(127);
+        let f = 256.0 / 2f32.powi(e);
+
+        w.write_all(&[(r * f) as u8, (g * f) as u8, (b * f) as u8, (e + 128) as u8])?;
+    }
+
+    Ok(())
+}
+
+// smallest valid openexr: single part scanline image, no compression, one line per block, 32 bit
+// float channels stored in alphabetical order
+fn write_exr<W: Write>(w: &mut W, width: u32, height: u32, rgb: &[f32]) -> std::io::Result<()> {
+    let mut channels = Vec::new();
+    for name in ["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        // pixel type FLOAT, pLinear and reserved bytes, x and y sampling
+        channels.extend_from_slice(&2i32.to_le_bytes());
+        channels.extend_from_slice(&[0, 0, 0, 0]);
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1].iter().flat_map(|v| v.to_le_bytes()).collect();
+
+    // magic number and version 2 without any flags
+    let mut header = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+
+    exr_attribute(&mut header, "channels", "chlist", &channels);
+    exr_attribute(&mut header, "compression", "compression", &[0]);
+    exr_attribute(&mut header, "dataWindow", "box2i", &window);
+    exr_attribute(&mut header, "displayWindow", "box2i", &window);
+    exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    exr_attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    exr_attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+
+    w.write_all(&header)?;
+
+    // the offset table points at every scanline block, which is y, byte count and the channel data
+    let line_size = 3 * 4 * width as usize;
+    let first_line = header.len() + 8 * height as usize;
+
+    for y in 0..height as usize {
+        w.write_all(&((first_line + y * (8 + line_size)) as u64).to_le_bytes())?;
+    }
+
+    for (y, row) in rgb.chunks(3 * width as usize).enumerate() {
+        w.write_all(&(y as i32).to_le_bytes())?;
+        w.write_all(&(line_size as i32).to_le_bytes())?;
+
+        for channel in [2, 1, 0] {
+            for px in row.chunks(3) {
+                w.write_all(&px[channel].to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn exr_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
@@ -0,0 +1,66 @@
+use rand::{RngCore, Error};
+
+// pcg32 (xsh rr variant). Every sample of every pixel gets its own stream derived from the seed, so
+// the image doesn't depend on which thread rendered what or in which order
+#[derive(Debug, Clone)]
+pub struct RandomStream {
+    state: u64,
+    increment: u64
+}
+
+const MULTIPLIER: u64 = 6364136223846793005;
+
+impl RandomStream {
+    pub fn new(seed: u64, stream: u64) -> RandomStream {
+        let mut rng = RandomStream { state: 0, increment: (stream << 1) | 1 };
+
+        rng.step();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.step();
+
+        rng
+    }
+
+    // stream for sample number `sample` of pixel `pixel`, counting every sample the pixel ever got so a
+    // resumed render doesn't take the same samples again
+    pub fn for_sample(seed: u64, pixel: u64, sample: u64) -> RandomStream {
+        RandomStream::new(splitmix64(seed ^ splitmix64(pixel ^ splitmix64(sample))), pixel)
+    }
+
+    fn step(&mut self) {
+        self.state = self.state.wrapping_mul(MULTIPLIER).wrapping_add(self.increment);
+    }
+}
+
+impl RngCore for RandomStream {
+    fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.step();
+
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next_u32() as u64) | (self.next_u32() as u64) << 32
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            chunk.copy_from_slice(&self.next_u32().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// scrambles nearby inputs (consecutive pixels and samples) into unrelated seeds
+pub(crate) fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,389 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use rayon::prelude::*;
+
+use crate::{
+    vec3::{Vec3, dot_product, unit_vector},
+    ray::Ray,
+    hittable::{Hit, HitRecord, World},
+    environment::Environment,
+    scene::Scene,
+    film::{Film, FilmTile, Image},
+    filter::{Filter, FilterKind},
+    sampler::{Sampler, SamplerKind},
+    tiles::{tiles, Tile, TileOrder}
+};
+
+// everything about a render that isn't the scene itself
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    /// size of the image `render` makes, `render_film` renders at the size of the film it's given
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: u64,
+    /// most bounces a path takes
+    pub max_depth: u64,
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    /// passes of 1, 2, 4, ... samples per pixel instead of all at once
+    pub progressive: bool,
+    /// relative error below which a pixel stops getting samples, `samples_per_pixel` is then the most any pixel gets
+    pub adaptive: Option<f64>,
+    /// samples every pixel gets before `adaptive` looks at it
+    pub min_samples: u64,
+    pub seed: u64,
+    pub filter: Filter,
+    pub sampler: SamplerKind
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            width: 1280,
+            height: 720,
+            samples_per_pixel: 100,
+            max_depth: 12,
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            progressive: false,
+            adaptive: None,
+            min_samples: 16,
+            seed: 0,
+            filter: Filter::new(FilterKind::Box, FilterKind::Box.default_radius()),
+            sampler: SamplerKind::Independent
+        }
+    }
+}
+
+// where a render is at, handed to the caller after every finished tile
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    /// samples taken in the tile that just finished
+    pub samples: u64,
+    pub tiles_done: usize,
+    pub tiles: usize
+}
+
+pub struct Renderer {
+    pub scene: Scene,
+    pub settings: RenderSettings
+}
+
+impl Renderer {
+    pub fn new(scene: Scene, settings: RenderSettings) -> Renderer {
+        Renderer { scene, settings }
+    }
+
+    // the whole image in one go, averaged and unclamped
+    pub fn render(&self) -> Image {
+        let mut film = Film::new(self.settings.width, self.settings.height);
+
+        self.render_film(&mut film, &AtomicBool::new(false), |_| (), |_| ());
+
+        film.image()
+    }
+
+    // how many more samples pixel `i` wants, with adaptive sampling none once it has converged
+    fn wanted(&self, film: &Film, i: usize) -> u64 {
+        let n = film.samples[i];
+        let spp = self.settings.samples_per_pixel;
+
+        match self.settings.adaptive {
+            Some(threshold) if n >= self.settings.min_samples.min(spp) && film.relative_error(i) < threshold => 0,
+            _ => spp.saturating_sub(n)
+        }
+    }
+
+    // samples still to be taken before `film` is done
+    pub fn remaining(&self, film: &Film) -> u64 {
+        (0..film.samples.len()).map(|i| self.wanted(film, i)).sum()
+    }
+
+    // keeps adding samples to `film` (fresh or a saved one being resumed) until every pixel has what it
+    // wants or `stop` is set. `on_tile` is called from the render threads after every tile, `on_pass`
+    // after every pass over the whole image, e.g. to write a checkpoint
+    pub fn render_film<T, P>(&self, film: &mut Film, stop: &AtomicBool, on_tile: T, mut on_pass: P)
+    where
+        T: Fn(&Progress) + Sync,
+        P: FnMut(&Film)
+    {
+        let settings = &self.settings;
+
+        // adaptive renders need passes to look at the error in between
+        let mut pass_samples: u64 = match (settings.progressive, settings.adaptive) {
+            (true, _) => 1,
+            (false, Some(_)) => settings.min_samples.min(settings.samples_per_pixel).max(1),
+            (false, None) => settings.samples_per_pixel
+        };
+
+        let tiles = tiles(film.width, film.height, settings.tile_size, settings.tile_order);
+
+        while self.remaining(film) > 0 && !stop.load(Ordering::Relaxed) {
+            let tiles_done = &AtomicUsize::new(0);
+            let current = &*film;
+
+            // every pixel gets at most `pass_samples` more and never more than it wants, which also evens
+            // out pixels a previous stopped run got further on. par_bridge hands the tiles out in order
+            let mut finished: Vec<(usize, FilmTile)> = tiles.iter().enumerate().par_bridge().map(|(n, tile)| {
+                let (samples, film_tile) = self.render_tile(tile, current, pass_samples, stop);
+
+                on_tile(&Progress { samples, tiles_done: tiles_done.fetch_add(1, Ordering::Relaxed) + 1, tiles: tiles.len() });
+
+                (n, film_tile)
+            }).collect();
+
+            // filtered tiles overlap their neighbours, adding them in the same order every time keeps the
+            // float sums independent of which thread finished first
+            finished.sort_unstable_by_key(|(n, _)| *n);
+
+            for (_, film_tile) in finished.iter() {
+                film.merge(film_tile);
+            }
+
+            pass_samples *= 2;
+
+            on_pass(film);
+        }
+    }
+
+    // renders one pass of `tile` on top of what `film` has so far, returns the number of samples taken
+    // and the tile's samples for merging into the film
+    fn render_tile(&self, tile: &Tile, film: &Film, pass_samples: u64, stop: &AtomicBool) -> (u64, FilmTile) {
+        let settings = &self.settings;
+        let scene = &self.scene;
+        let (width, height) = (film.width, film.height);
+
+        // (samples taken so far, samples to take now) for every pixel of the tile
+        let needed: Vec<(u64, u64)> = (tile.y0..tile.y1).flat_map(|y| {
+            let row = (y * width) as usize;
+            (row + tile.x0 as usize..row + tile.x1 as usize).map(|i| (film.samples[i], pass_samples.min(self.wanted(film, i)))).collect::<Vec<(u64, u64)>>()
+        }).collect();
+        let mut film_tile = film.tile(tile, settings.filter);
+
+        let mut sampler = settings.sampler.build(settings.seed, settings.samples_per_pixel);
+        let mut taken = 0;
+
+        for (k, (taken_before, samples)) in needed.into_iter().enumerate() {
+            let i = tile.x0 + k as u32 % tile.width();
+            let y = tile.y0 + k as u32 / tile.width();
+            let j = height - 1 - y;
+            let pixel = (y * width + i) as u64;
+
+            for s in 0..samples {
+                if stop.load(Ordering::Relaxed) {
+                    break
+                }
+
+                sampler.start_sample(pixel, taken_before + s);
+
+                let (dx, dy) = sampler.next_2d();
+                let u = (i as f64 + dx) / (width - 1) as f64;
+                let v = (j as f64 + dy) / (height - 1) as f64;
+
+                let color = match scene.camera.get_ray(u, v, sampler.as_mut()) {
+                    Some(r) => ray_color(r, scene.world.as_ref(), &scene.lights, scene.environment.as_ref(), settings.max_depth, sampler.as_mut()),
+                    None => Vec3::new(0.0, 0.0, 0.0)
+                };
+
+                // v goes up the image, the film counts rows down from the top
+                film_tile.add_sample(i, y, dx, 1.0 - dy, color);
+                taken += 1;
+            }
+        }
+
+        (taken, film_tile)
+    }
+}
+
+fn ray_color(mut ray: Ray, world: &dyn Hit, lights: &World, environment: &dyn Environment, depth: u64, sampler: &mut dyn Sampler) -> Vec3 {
+    let mut color = Vec3::new(0.0, 0.0, 0.0);
+    let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+
+    // density the material picked the current ray with, None for camera rays and specular bounces
+    let mut scatter_pdf: Option<f64> = None;
+
+    for _ in 0..depth {
+        // 0.1e-325
+        let Some(rec) = world.hit(&ray, 0.001, f64::INFINITY) else {
+            // an importance sampled environment is one of the lights, weigh it the same way
+            let weight = match scatter_pdf {
+                Some(pdf) => power_heuristic(pdf, light_pdf(&ray, lights, environment)),
+                None => 1.0
+            };
+
+            color += throughput * environment.value(&ray.direction) * weight;
+            break
+        };
+
+        let emitted = rec.mat.emitted(rec.u, rec.v, &rec.p);
+
+        if !emitted.near_zero() {
+            // this light could also have been reached by the light sample at the previous bounce
+            let weight = match scatter_pdf {
+                Some(pdf) => power_heuristic(pdf, light_pdf(&ray, lights, environment)),
+                None => 1.0
+            };
+
+            color += throughput * emitted * weight;
+        }
+
+        let Some(srec) = rec.mat.scatter(&ray, &rec, sampler) else {
+            break
+        };
+
+        if !srec.specular && environment_chance(lights, environment).is_some() {
+            color += throughput * sample_lights(&ray, &rec, world, lights, environment, sampler);
+            scatter_pdf = Some(srec.pdf);
+        } else {
+            scatter_pdf = None;
+        }
+
+        throughput = throughput * srec.attenuation;
+        ray = srec.ray;
+    }
+
+    color
+}
+
+// how often a light sample goes to the environment instead of the scene lights, None when there
+// is nothing to sample at all
+fn environment_chance(lights: &World, environment: &dyn Environment) -> Option<f64> {
+    match (lights.is_empty(), environment.sampled()) {
+        (true, false) => None,
+        (true, true) => Some(1.0),
+        (false, false) => Some(0.0),
+        (false, true) => Some(0.5)
+    }
+}
+
+// solid angle density of `sample_lights` picking the direction of `ray`
+fn light_pdf(ray: &Ray, lights: &World, environment: &dyn Environment) -> f64 {
+    let Some(chance) = environment_chance(lights, environment) else {
+        return 0.0
+    };
+
+    let mut pdf = 0.0;
+
+    if chance < 1.0 {
+        pdf += (1.0 - chance) * lights.pdf_value(&ray.origin, &ray.direction);
+    }
+
+    if chance > 0.0 {
+        pdf += chance * environment.pdf_value(&ray.direction);
+    }
+
+    pdf
+}
+
+// next event estimation, shoot a shadow ray at a random point on a light (or a bright part of the
+// environment) and weight it against the chance of the material having scattered toward it on its own
+fn sample_lights(ray: &Ray, rec: &HitRecord, world: &dyn Hit, lights: &World, environment: &dyn Environment, sampler: &mut dyn Sampler) -> Vec3 {
+    let chance = environment_chance(lights, environment).unwrap_or(0.0);
+
+    let direction = if sampler.next_1d() < chance {
+        environment.random(sampler)
+    } else {
+        lights.random(&rec.p, sampler)
+    };
+
+    let light_ray = Ray::new(rec.p, direction, ray.time);
+    let pdf = light_pdf(&light_ray, lights, environment);
+
+    if pdf <= 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    let bsdf = rec.mat.eval(ray, rec, &light_ray.direction);
+
+    if bsdf.near_zero() {
+        return Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    // whichever one picked the direction, the radiance along it comes from the closest light, or the
+    // environment if it gets out, find that first and only then ask the world if anything is in between
+    let emitted = match lights.hit(&light_ray, 0.001, f64::INFINITY) {
+        Some(light_rec) => {
+            if world.occluded(&light_ray, 0.001, light_rec.t * (1.0 - 1e-4)) {
+                return Vec3::new(0.0, 0.0, 0.0)
+            }
+
+            light_rec.mat.emitted(light_rec.u, light_rec.v, &light_rec.p)
+        },
+        None if chance > 0.0 => {
+            if world.occluded(&light_ray, 0.001, f64::INFINITY) {
+                return Vec3::new(0.0, 0.0, 0.0)
+            }
+
+            environment.value(&light_ray.direction)
+        },
+        None => return Vec3::new(0.0, 0.0, 0.0)
+    };
+
+    if emitted.near_zero() {
+        return Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    let cosine = dot_product(&rec.normal, &unit_vector(&light_ray.direction)).abs();
+    let scatter_pdf = rec.mat.pdf(ray, rec, &light_ray.direction);
+
+    bsdf * emitted * (cosine * power_heuristic(pdf, scatter_pdf) / pdf)
+}
+
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+
+    a / (a + b)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{scene::SceneFile, bvh::BVHOptions};
+
+    fn render(threads: usize, settings: RenderSettings) -> Image {
+        let desc: SceneFile = toml::from_str(include_str!("../scenes/cornell.toml")).unwrap();
+        let scene = Scene::from_desc(&desc, Path::new("scenes"), settings.width as f64 / settings.height as f64, &BVHOptions::default()).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+
+        pool.install(|| Renderer::new(scene, settings).render())
+    }
+
+    #[test]
+    fn same_seed_renders_the_same_image_on_any_number_of_threads() {
+        // small tiles and a wide filter so lots of tiles overlap, progressive so passes add onto each other
+        let settings = RenderSettings {
+            width: 24,
+            height: 16,
+            samples_per_pixel: 8,
+            max_depth: 4,
+            tile_size: 4,
+            progressive: true,
+            seed: 1234,
+            filter: Filter::new(FilterKind::Mitchell, 2.0),
+            ..Default::default()
+        };
+
+        let first = render(4, settings);
+        let second = render(4, settings);
+        let single = render(1, settings);
+
+        assert!(first.rgb.iter().any(|c| *c > 0.0));
+        assert_eq!(first.rgb, second.rgb);
+        assert_eq!(first.rgb, single.rgb);
+    }
+
+    #[test]
+    fn render_film_renders_at_the_size_of_the_film() {
+        let settings = RenderSettings { width: 8, height: 8, samples_per_pixel: 1, ..Default::default() };
+        let desc: SceneFile = toml::from_str(include_str!("../scenes/cornell.toml")).unwrap();
+        let scene = Scene::from_desc(&desc, Path::new("scenes"), 1.0, &BVHOptions::default()).unwrap();
+        let mut film = Film::new(4, 6);
+
+        Renderer::new(scene, settings).render_film(&mut film, &AtomicBool::new(false), |_| (), |_| ());
+
+        assert!(film.samples.iter().all(|&n| n == 1));
+    }
+}
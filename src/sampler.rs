@@ -0,0 +1,365 @@
+use clap::ValueEnum;
+use rand::Rng;
+
+use crate::random::{RandomStream, splitmix64};
+
+// hands out the random numbers of one sample of one pixel, one dimension after the other. The camera
+// takes the first few, then every bounce takes what its light and material sampling need, so the same
+// dimension of different samples of a pixel is used for the same decision and can be spread evenly
+pub trait Sampler: Send {
+    // every value from here on belongs to sample number `index` of pixel `pixel`
+    fn start_sample(&mut self, pixel: u64, index: u64);
+
+    fn next_1d(&mut self) -> f64;
+
+    fn next_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SamplerKind {
+    /// uniform random numbers, no two samples know about each other
+    Independent,
+    /// jittered strata, every sample of a pixel lands in its own cell of a grid
+    Stratified,
+    /// halton sequence shifted randomly per pixel
+    Halton,
+    /// owen scrambled sobol points, padded pairwise for the higher dimensions
+    Sobol
+}
+
+impl SamplerKind {
+    // `samples_per_pixel` is what the stratified sampler sizes its grid for
+    pub fn build(&self, seed: u64, samples_per_pixel: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed))
+        }
+    }
+}
+
+pub struct IndependentSampler {
+    seed: u64,
+    rng: RandomStream
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler { seed, rng: RandomStream::new(seed, 0) }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, pixel: u64, index: u64) {
+        self.rng = RandomStream::for_sample(self.seed, pixel, index);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        self.rng.gen::<f64>()
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.rng.gen::<f64>(), self.rng.gen::<f64>())
+    }
+}
+
+// the samples of a pixel go through the strata of every dimension in a different shuffled order. Once
+// a pixel has more samples than strata (progressive or adaptive renders), the next round starts over
+// with a new shuffle
+pub struct StratifiedSampler {
+    seed: u64,
+    strata: u64,
+    /// cells along each side of the 2d grid
+    side: u64,
+    pixel: u64,
+    index: u64,
+    dimension: u64,
+    rng: RandomStream
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: u64) -> StratifiedSampler {
+        let side = ((samples_per_pixel.max(1) as f64).sqrt().ceil() as u64).max(1);
+
+        StratifiedSampler { seed, strata: samples_per_pixel.max(1), side, pixel: 0, index: 0, dimension: 0, rng: RandomStream::new(seed, 0) }
+    }
+
+    // stratum of the current sample in the next dimension, out of `count`
+    fn stratum(&mut self, count: u64) -> u64 {
+        let round = self.index / count;
+        let key = splitmix64(self.seed ^ splitmix64(self.pixel ^ splitmix64(self.dimension ^ splitmix64(round))));
+        self.dimension += 1;
+
+        permute(self.index % count, count, key)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, pixel: u64, index: u64) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+        self.rng = RandomStream::for_sample(self.seed, pixel, index);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let stratum = self.stratum(self.strata);
+
+        (stratum as f64 + self.rng.gen::<f64>()) / self.strata as f64
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let stratum = self.stratum(self.side * self.side);
+        let (x, y) = (stratum % self.side, stratum / self.side);
+
+        (
+            (x as f64 + self.rng.gen::<f64>()) / self.side as f64,
+            (y as f64 + self.rng.gen::<f64>()) / self.side as f64
+        )
+    }
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131
+];
+
+// halton points with the digits of every dimension shuffled per pixel, so neighbouring pixels don't
+// repeat each other and the large bases of the higher dimensions still spread out over the first few
+// samples. Past the last prime the dimensions are plain random numbers
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: u64,
+    index: u64,
+    dimension: usize,
+    rng: RandomStream
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler { seed, pixel: 0, index: 0, dimension: 0, rng: RandomStream::new(seed, 0) }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, pixel: u64, index: u64) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+        self.rng = RandomStream::for_sample(self.seed, pixel, index);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+
+        let Some(base) = PRIMES.get(dimension) else {
+            return self.rng.gen::<f64>()
+        };
+
+        let key = splitmix64(self.seed ^ splitmix64(self.pixel ^ splitmix64(dimension as u64)));
+
+        scrambled_radical_inverse(*base, self.index, key)
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+// the first two sobol dimensions, owen scrambled and with the sample index shuffled differently for
+// every pair of dimensions (Burley 2020, practical hash-based owen scrambling). Pairs are well
+// distributed among themselves, different pairs are independent of each other
+pub struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    index: u64,
+    dimension: u64
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        SobolSampler { seed, pixel: 0, index: 0, dimension: 0 }
+    }
+
+    fn next_seed(&mut self) -> u32 {
+        let key = splitmix64(self.seed ^ splitmix64(self.pixel ^ splitmix64(self.dimension)));
+        self.dimension += 1;
+
+        key as u32
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, pixel: u64, index: u64) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let key = self.next_seed();
+        let index = owen_scramble(self.index as u32, key);
+
+        to_unit(owen_scramble(index.reverse_bits(), splitmix64(key as u64) as u32))
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let key = self.next_seed();
+        let index = owen_scramble(self.index as u32, key);
+
+        let x = owen_scramble(index.reverse_bits(), splitmix64(key as u64) as u32);
+        let y = owen_scramble(sobol_second_dimension(index), splitmix64(key as u64 ^ 1) as u32);
+
+        (to_unit(x), to_unit(y))
+    }
+}
+
+// digits of `index` in `base` mirrored around the decimal point, every digit position going through its
+// own permutation. The leading zeros are permuted too, down to about 32 bits of precision
+fn scrambled_radical_inverse(base: u64, mut index: u64, key: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut result = 0.0;
+    let mut scale = inverse_base;
+    let mut position = 0;
+
+    while scale > 1.0 / 4294967296.0 {
+        let digit = permute(index % base, base, splitmix64(key ^ position));
+
+        result += digit as f64 * scale;
+        index /= base;
+        scale *= inverse_base;
+        position += 1;
+    }
+
+    result.min(1.0 - f64::EPSILON)
+}
+
+// second sobol dimension, from the primitive polynomial x + 1
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1u32 << 31;
+
+    while index > 0 {
+        if index & 1 == 1 {
+            result ^= direction;
+        }
+
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+
+    result
+}
+
+// nested uniform scramble of the bits of `x`, every bit is flipped depending on all the bits above it
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+
+    // laine-karras style permutation, each step only carries toward the higher bits
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+
+    x.reverse_bits()
+}
+
+// `i` shuffled among 0..count by a keyed permutation (Kensler 2013, correlated multi-jittered sampling)
+fn permute(i: u64, count: u64, key: u64) -> u64 {
+    let (mut i, l, p) = (i as u32, count as u32, key as u32);
+
+    if l <= 1 {
+        return 0
+    }
+
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    // a bijection on 0..=w, applied again until the result lands below count
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < l {
+            return (i.wrapping_add(p) % l) as u64
+        }
+    }
+}
+
+// `x` as a fraction of 2^32, in [0, 1)
+fn to_unit(x: u32) -> f64 {
+    x as f64 / 4294967296.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permute_is_a_bijection() {
+        for count in [1, 2, 3, 7, 16, 100, 1000] {
+            for key in [0, 1, 0xdeadbeef, splitmix64(count)] {
+                let mut seen = vec![false; count as usize];
+
+                for i in 0..count {
+                    let p = permute(i, count, key);
+                    assert!(p < count, "permute({}, {}, {}) = {}", i, count, key, p);
+                    assert!(!seen[p as usize], "permute hit {} twice for count {} and key {}", p, count, key);
+                    seen[p as usize] = true;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn owen_scramble_permutes_every_level_of_strata() {
+        for seed in [0, 1, 0x9e3779b9, 0xffffffff] {
+            for bits in [1, 4, 8] {
+                let mut seen = vec![false; 1 << bits];
+
+                // the top `bits` bits of the result only depend on the top `bits` bits of the input
+                for i in 0..1u32 << bits {
+                    let stratum = owen_scramble(i << (32 - bits), seed) >> (32 - bits);
+                    assert!(!seen[stratum as usize], "stratum {} hit twice with seed {}", stratum, seed);
+                    seen[stratum as usize] = true;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_samples_cover_every_stratum_once() {
+        let mut sampler = StratifiedSampler::new(7, 16);
+        let mut seen = [false; 16];
+
+        for index in 0..16 {
+            sampler.start_sample(42, index);
+            let stratum = (sampler.next_1d() * 16.0) as usize;
+            assert!(!seen[stratum], "stratum {} hit twice", stratum);
+            seen[stratum] = true;
+        }
+    }
+}
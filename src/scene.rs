@@ -0,0 +1,328 @@
+use std::{collections::HashMap, fs, path::{Path, PathBuf}, sync::Arc};
+
+use serde::Deserialize;
+
+use crate::{
+    vec3::{Vec3, Point3},
+    hittable::{Hit, World},
+    sphere::{Sphere, MovingSphere},
+    triangle::Triangle,
+    camera::{Camera, View, Projection, FisheyeModel, PerspectiveCamera, OrthographicCamera, EquirectangularCamera, FisheyeCamera},
+    material::{Scatter, Lambertian, Metal, Dielectric, DiffuseLight},
+    texture::{Texture, ConstantTexture, CheckerTexture},
+    obj::{load_obj_and_position, add_obj_to_world},
+    bvh::BVHOptions,
+    transform::Matrix4,
+    environment::{Environment, ConstantEnvironment, GradientEnvironment, ImageEnvironment},
+    error::{Error, Result}
+};
+
+/// Top level layout of a scene file, see `scenes/` for examples
+#[derive(Deserialize)]
+pub struct SceneFile {
+    pub camera: CameraDesc,
+    /// what rays that escape the scene see, the sky gradient is used when unset
+    pub background: Option<BackgroundDesc>,
+    #[serde(default)]
+    pub textures: HashMap<String, TextureDesc>,
+    #[serde(default)]
+    pub materials: HashMap<String, MaterialDesc>,
+    #[serde(default)]
+    pub objects: Vec<ObjectDesc>
+}
+
+#[derive(Deserialize)]
+pub struct CameraDesc {
+    pub lookfrom: [f64; 3],
+    pub lookat: [f64; 3],
+    #[serde(default = "default_vup")]
+    pub vup: [f64; 3],
+    #[serde(default = "default_vfov")]
+    pub vfov: f64,
+    #[serde(default)]
+    pub aperture: f64,
+    /// defaults to the distance between `lookfrom` and `lookat`
+    pub focus_dist: Option<f64>,
+    #[serde(default)]
+    pub time0: f64,
+    #[serde(default = "default_time1")]
+    pub time1: f64,
+    #[serde(default = "default_projection")]
+    pub projection: Projection,
+    /// height of the orthographic view, defaults to what the perspective camera sees at `focus_dist`
+    pub view_height: Option<f64>,
+    #[serde(default = "default_fisheye_model")]
+    pub fisheye_model: FisheyeModel,
+    /// degrees across the fisheye image circle
+    #[serde(default = "default_fisheye_fov")]
+    pub fisheye_fov: f64
+}
+
+impl CameraDesc {
+    pub fn build(&self, aspect_ratio: f64) -> Box<dyn Camera> {
+        let view = View {
+            origin: Point3::from_slice(self.lookfrom),
+            lookat: Point3::from_slice(self.lookat),
+            vup: Vec3::from_slice(self.vup),
+            time0: self.time0,
+            time1: self.time1
+        };
+        let focus_dist = self.focus_dist.unwrap_or_else(|| (view.origin - view.lookat).length());
+
+        match self.projection {
+            Projection::Perspective => Box::new(PerspectiveCamera::new(&view, self.vfov, aspect_ratio, self.aperture, focus_dist)),
+            Projection::Orthographic => {
+                let view_height = self.view_height.unwrap_or_else(|| 2.0 * (self.vfov.to_radians() / 2.0).tan() * focus_dist);
+                Box::new(OrthographicCamera::new(&view, view_height, aspect_ratio))
+            },
+            Projection::Equirectangular => Box::new(EquirectangularCamera::new(&view)),
+            Projection::Fisheye => Box::new(FisheyeCamera::new(&view, self.fisheye_model, self.fisheye_fov, aspect_ratio))
+        }
+    }
+}
+
+fn default_projection() -> Projection { Projection::Perspective }
+fn default_fisheye_model() -> FisheyeModel { FisheyeModel::Equidistant }
+fn default_fisheye_fov() -> f64 { 180.0 }
+fn default_vup() -> [f64; 3] { [0.0, 1.0, 0.0] }
+fn default_vfov() -> f64 { 40.0 }
+fn default_time1() -> f64 { 1.0 }
+
+/// either a flat color or a `[background]` table
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum BackgroundDesc {
+    Color([f64; 3]),
+    Environment(EnvironmentDesc)
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EnvironmentDesc {
+    Constant { color: [f64; 3] },
+    Gradient { bottom: [f64; 3], top: [f64; 3] },
+    /// equirectangular radiance `.hdr`, path is relative to the scene file
+    Image { path: String, #[serde(default = "default_strength")] strength: f64 }
+}
+
+fn default_strength() -> f64 { 1.0 }
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextureDesc {
+    Constant { color: [f64; 3] },
+    Checker { odd: TextureRef, even: TextureRef }
+}
+
+/// a texture can be given as a plain color, the name of an entry in `[textures]`, or inline
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum TextureRef {
+    Color([f64; 3]),
+    Named(String),
+    Inline(Box<TextureDesc>)
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialDesc {
+    Lambertian { albedo: TextureRef },
+    Metal { albedo: [f64; 3], #[serde(default)] fuzz: f64 },
+    Dielectric { ir: f64 },
+    DiffuseLight { emit: TextureRef }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ObjectDesc {
+    Sphere { center: [f64; 3], radius: f64, material: String },
+    MovingSphere { center0: [f64; 3], center1: [f64; 3], radius: f64, material: String, #[serde(default)] time0: f64, #[serde(default = "default_time1")] time1: f64 },
+    Triangle { v0: [f64; 3], v1: [f64; 3], v2: [f64; 3], material: String },
+    /// parallelogram with corner `q` and edges `u` and `v`, split into two triangles
+    Quad { q: [f64; 3], u: [f64; 3], v: [f64; 3], material: String },
+    /// path is relative to the scene file, the same file listed more than once is loaded once and instanced
+    Obj {
+        path: String,
+        #[serde(default)]
+        translate: [f64; 3],
+        /// degrees around x, y and z, applied in that order
+        #[serde(default)]
+        rotate: [f64; 3],
+        #[serde(default = "default_scale")]
+        scale: f64
+    }
+}
+
+fn default_scale() -> f64 { 1.0 }
+
+pub struct Scene {
+    pub world: Box<dyn Hit>,
+    pub lights: World,
+    pub camera: Box<dyn Camera>,
+    pub environment: Box<dyn Environment>
+}
+
+impl Scene {
+    // `projection` overrides the one in the scene file when set
+    pub fn load(path: &Path, aspect_ratio: f64, projection: Option<Projection>, bvh_options: &BVHOptions) -> Result<Scene> {
+        let src = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        let mut desc: SceneFile = toml::from_str(&src).map_err(|e| Error::invalid(path, e))?;
+
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+
+        if let Some(projection) = projection {
+            desc.camera.projection = projection;
+        }
+
+        Scene::from_desc(&desc, base_dir, aspect_ratio, bvh_options)
+    }
+
+    pub fn from_desc(desc: &SceneFile, base_dir: &Path, aspect_ratio: f64, bvh_options: &BVHOptions) -> Result<Scene> {
+        let camera = desc.camera.build(aspect_ratio);
+
+        let mut textures: HashMap<&str, Arc<dyn Texture>> = HashMap::new();
+        for (name, tex) in desc.textures.iter() {
+            let built = build_texture(tex, &desc.textures, &mut vec![name.as_str()])?;
+            textures.insert(name, built);
+        }
+
+        let materials: HashMap<&str, Arc<dyn Scatter>> = desc.materials.iter().map(|(name, mat)| {
+            let built: Arc<dyn Scatter> = match mat {
+                MaterialDesc::Lambertian { albedo } => Arc::new(Lambertian::new(resolve_texture(albedo, &textures, &desc.textures)?)),
+                MaterialDesc::Metal { albedo, fuzz } => Arc::new(Metal::new(Vec3::from_slice(*albedo), *fuzz)),
+                MaterialDesc::Dielectric { ir } => Arc::new(Dielectric::new(*ir)),
+                MaterialDesc::DiffuseLight { emit } => Arc::new(DiffuseLight::new(resolve_texture(emit, &textures, &desc.textures)?))
+            };
+
+            Ok((name.as_str(), built))
+        }).collect::<Result<_>>()?;
+
+        let material = |name: &String| -> Result<Arc<dyn Scatter>> {
+            match materials.get(name.as_str()) {
+                Some(mat) => Ok(Arc::clone(mat)),
+                None => Err(Error::Scene(format!("scene references unknown material \"{}\"", name)))
+            }
+        };
+
+        let mut world: World = Vec::new();
+        // emissive primitives are also added here so the integrator can sample them directly
+        let mut lights: World = Vec::new();
+        let mut meshes: HashMap<PathBuf, Arc<dyn Hit>> = HashMap::new();
+
+        for obj in desc.objects.iter() {
+            match obj {
+                ObjectDesc::Sphere { material: m, .. } |
+                ObjectDesc::MovingSphere { material: m, .. } |
+                ObjectDesc::Triangle { material: m, .. } |
+                ObjectDesc::Quad { material: m, .. } => {
+                    push_primitive(&mut world, obj, material(m)?);
+
+                    if let Some(MaterialDesc::DiffuseLight { .. }) = desc.materials.get(m) {
+                        push_primitive(&mut lights, obj, material(m)?);
+                    }
+                },
+                ObjectDesc::Obj { path, translate, rotate, scale } => {
+                    let path = base_dir.join(path);
+                    let mesh = match meshes.get(&path) {
+                        Some(mesh) => Arc::clone(mesh),
+                        None => {
+                            let mesh = load_obj_and_position(&path, bvh_options)?;
+                            meshes.insert(path, Arc::clone(&mesh));
+                            mesh
+                        }
+                    };
+
+                    let position = Matrix4::from_trs(Vec3::from_slice(*translate), Vec3::from_slice(*rotate), Vec3::new(*scale, *scale, *scale));
+                    add_obj_to_world(&mut world, &mesh, position)?;
+                }
+            }
+        }
+
+        // nothing to build a bvh over, the background alone still renders like it does for the demo scene
+        let world: Box<dyn Hit> = if world.is_empty() {
+            Box::new(world)
+        } else {
+            bvh_options.build(world, desc.camera.time0, desc.camera.time1)?
+        };
+
+        Ok(Scene {
+            world,
+            lights,
+            camera,
+            environment: build_environment(desc.background.as_ref(), base_dir)?
+        })
+    }
+}
+
+fn push_primitive(world: &mut World, obj: &ObjectDesc, mat: Arc<dyn Scatter>) {
+    match obj {
+        ObjectDesc::Sphere { center, radius, .. } => {
+            world.push(Box::new(Sphere::new(Point3::from_slice(*center), *radius, mat)));
+        },
+        ObjectDesc::MovingSphere { center0, center1, radius, time0, time1, .. } => {
+            world.push(Box::new(MovingSphere::new(Point3::from_slice(*center0), Point3::from_slice(*center1), *radius, mat, *time0, *time1)));
+        },
+        ObjectDesc::Triangle { v0, v1, v2, .. } => {
+            world.push(Box::new(Triangle::new(Vec3::from_slice(*v0), Vec3::from_slice(*v1), Vec3::from_slice(*v2), mat)));
+        },
+        ObjectDesc::Quad { q, u, v, .. } => {
+            let (q, u, v) = (Point3::from_slice(*q), Vec3::from_slice(*u), Vec3::from_slice(*v));
+
+            world.push(Box::new(Triangle::new(q, q + u, q + u + v, Arc::clone(&mat))));
+            world.push(Box::new(Triangle::new(q, q + u + v, q + v, mat)));
+        },
+        ObjectDesc::Obj { .. } => unreachable!("meshes are loaded through the obj loader")
+    }
+}
+
+fn build_environment(desc: Option<&BackgroundDesc>, base_dir: &Path) -> Result<Box<dyn Environment>> {
+    Ok(match desc {
+        None => Box::new(GradientEnvironment::sky()),
+        Some(BackgroundDesc::Color(color)) |
+        Some(BackgroundDesc::Environment(EnvironmentDesc::Constant { color })) => Box::new(ConstantEnvironment::new(Vec3::from_slice(*color))),
+        Some(BackgroundDesc::Environment(EnvironmentDesc::Gradient { bottom, top })) => Box::new(GradientEnvironment::new(Vec3::from_slice(*bottom), Vec3::from_slice(*top))),
+        Some(BackgroundDesc::Environment(EnvironmentDesc::Image { path, strength })) => Box::new(ImageEnvironment::load(&base_dir.join(path), *strength)?)
+    })
+}
+
+// `stack` holds the names currently being resolved so a checker referencing itself doesn't recurse forever
+fn build_texture<'a>(desc: &'a TextureDesc, named: &'a HashMap<String, TextureDesc>, stack: &mut Vec<&'a str>) -> Result<Arc<dyn Texture>> {
+    Ok(match desc {
+        TextureDesc::Constant { color } => Arc::new(ConstantTexture::new(Vec3::from_slice(*color))),
+        TextureDesc::Checker { odd, even } => Arc::new(CheckerTexture::new(
+            build_texture_ref(odd, named, stack)?,
+            build_texture_ref(even, named, stack)?
+        ))
+    })
+}
+
+fn build_texture_ref<'a>(tex: &'a TextureRef, named: &'a HashMap<String, TextureDesc>, stack: &mut Vec<&'a str>) -> Result<Arc<dyn Texture>> {
+    match tex {
+        TextureRef::Color(color) => Ok(Arc::new(ConstantTexture::new(Vec3::from_slice(*color)))),
+        TextureRef::Inline(desc) => build_texture(desc, named, stack),
+        TextureRef::Named(name) => {
+            if stack.contains(&name.as_str()) {
+                return Err(Error::Scene(format!("texture \"{}\" references itself", name)))
+            }
+
+            let desc = named.get(name).ok_or_else(|| unknown_texture(name))?;
+
+            stack.push(name);
+            let built = build_texture(desc, named, stack);
+            stack.pop();
+
+            built
+        }
+    }
+}
+
+fn resolve_texture(tex: &TextureRef, built: &HashMap<&str, Arc<dyn Texture>>, named: &HashMap<String, TextureDesc>) -> Result<Arc<dyn Texture>> {
+    match tex {
+        TextureRef::Named(name) => built.get(name.as_str()).map(Arc::clone).ok_or_else(|| unknown_texture(name)),
+        _ => build_texture_ref(tex, named, &mut Vec::new())
+    }
+}
+
+fn unknown_texture(name: &str) -> Error {
+    Error::Scene(format!("scene references unknown texture \"{}\"", name))
+}
@@ -1,6 +1,6 @@
 use std::{sync::Arc, simd::{Simd, f64x2}, f64::consts::PI};
 
-use crate::{vec3::{Point3, dot_product, Vec3}, hittable::{Hit, HitRecord}, ray::Ray, material::Scatter, aabb::AABB};
+use crate::{vec3::{Point3, dot_product, Vec3, random_unit_vector}, hittable::{Hit, HitRecord}, ray::Ray, material::Scatter, aabb::AABB, onb::Onb, sampler::Sampler};
 
 pub struct Sphere {
     pub center: Point3,
@@ -26,31 +26,38 @@ impl Sphere {
     }
 }
 
-impl Hit for Sphere {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = &r.origin - self.center;
-        let a = &r.direction.length_squared();
-        let half_b = dot_product(&oc, &r.direction);
-        let c = oc.length_squared() - self.radius*self.radius;
+// closest root of the ray/sphere quadratic inside [t_min, t_max]
+fn sphere_root(center: &Point3, radius: f64, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+    let oc = &r.origin - center;
+    let a = &r.direction.length_squared();
+    let half_b = dot_product(&oc, &r.direction);
+    let c = oc.length_squared() - radius*radius;
 
-        let fd = &half_b*&half_b;
-        let sd = a * &c;
-        let discriminant = &fd - &sd;
+    let fd = &half_b*&half_b;
+    let sd = a * &c;
+    let discriminant = &fd - &sd;
 
-        //let discriminant = half_b*half_b - a*c;
-        if &discriminant < &0.0 {
-            return None;
-        }
+    //let discriminant = half_b*half_b - a*c;
+    if &discriminant < &0.0 {
+        return None;
+    }
 
-        let sqrtd = &discriminant.sqrt();
-        let mut root = (-&half_b - sqrtd) / a;
+    let sqrtd = &discriminant.sqrt();
+    let mut root = (-&half_b - sqrtd) / a;
 
+    if &root < &t_min || &t_max < &root {
+        root = (-&half_b + sqrtd) / a;
         if &root < &t_min || &t_max < &root {
-            root = (-&half_b + sqrtd) / a;
-            if &root < &t_min || &t_max < &root {
-                return None;
-            }
+            return None;
         }
+    }
+
+    Some(root)
+}
+
+impl Hit for Sphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let root = sphere_root(&self.center, self.radius, r, t_min, t_max)?;
 
         let mut rec = HitRecord {
             t: root,
@@ -73,6 +80,10 @@ impl Hit for Sphere {
         Some(rec)
     }
 
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        sphere_root(&self.center, self.radius, r, t_min, t_max).is_some()
+    }
+
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
         Some(
             AABB::new(
@@ -81,6 +92,49 @@ impl Hit for Sphere {
             )
         )
     }
+
+    // samples the cone of directions subtended by the sphere, or the whole sphere of directions from inside it
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.hit(&Ray::new(*origin, *direction, 0.0), 0.001, f64::INFINITY).is_none() {
+            return 0.0
+        }
+
+        let distance_squared = (self.center - origin).length_squared();
+
+        if distance_squared <= self.radius * self.radius {
+            return 1.0 / (4.0 * PI)
+        }
+
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        let direction = self.center - origin;
+        let distance_squared = direction.length_squared();
+
+        if distance_squared <= self.radius * self.radius {
+            return random_unit_vector(sampler)
+        }
+
+        let uvw = Onb::build_from_w(&direction);
+
+        uvw.local(&random_to_sphere(self.radius, distance_squared, sampler))
+    }
+}
+
+// direction inside the cone around +z that covers a sphere of `radius` at `distance_squared` away
+fn random_to_sphere(radius: f64, distance_squared: f64, sampler: &mut dyn Sampler) -> Vec3 {
+    let (r1, r2) = sampler.next_2d();
+    let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+
+    let phi = 2.0 * PI * r1;
+    let x = phi.cos() * (1.0 - z * z).sqrt();
+    let y = phi.sin() * (1.0 - z * z).sqrt();
+
+    Vec3::new(x, y, z)
 }
 
 pub struct MovingSphere {
@@ -113,26 +167,7 @@ impl MovingSphere {
 
 impl Hit for MovingSphere {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = &r.origin - &self.center(&r.time);
-        let a = r.direction.length_squared();
-        let half_b = dot_product(&oc, &r.direction);
-        let c = &oc.length_squared() - &self.radius * &self.radius;
-
-        let discriminant = &half_b * &half_b - &a * &c;
-
-        if &discriminant < &0.0 {
-            return None;
-        }
-
-        let sqrtd = &discriminant.sqrt();
-        let mut root = (-&half_b - sqrtd) / &a;
-
-        if &root < &t_min || &t_max < &root {
-            root = (-&half_b + sqrtd) / &a;
-            if &root < &t_min || &t_max < &root {
-                return None;
-            }
-        }
+        let root = sphere_root(&self.center(&r.time), self.radius, r, t_min, t_max)?;
 
         let mut rec = HitRecord {
             t: root,
@@ -155,6 +190,10 @@ impl Hit for MovingSphere {
         Some(rec)
     }
 
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        sphere_root(&self.center(&r.time), self.radius, r, t_min, t_max).is_some()
+    }
+
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
         let box0 = AABB::new(
             &(self.center(&time0) - Vec3::new(self.radius, self.radius, self.radius)),
@@ -1,9 +1,18 @@
-use crate::{vec3::Vec3};
+use std::{fs::File, path::Path, sync::Arc};
+
+use crate::{vec3::Vec3, error::{Error, Result}};
 
 pub trait Texture: Sync + Send {
     fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3;
 }
 
+// lets textures built at runtime (e.g. from a scene file) be shared between materials
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        (**self).value(u, v, p)
+    }
+}
+
 pub struct ConstantTexture {
     color: Vec3
 }
@@ -36,4 +45,105 @@ impl<T: Texture, Y: Texture> Texture for CheckerTexture<T, Y> {
             self.even.value(u, v, p)
         }
     }
+}
+
+// what happens to uvs outside of [0, 1]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WrapMode {
+    Repeat,
+    Mirror,
+    Clamp
+}
+
+impl WrapMode {
+    fn apply(&self, i: i64, size: usize) -> usize {
+        let n = size as i64;
+
+        match self {
+            WrapMode::Repeat => i.rem_euclid(n) as usize,
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * n);
+                (if i < n { i } else { 2 * n - 1 - i }) as usize
+            },
+            WrapMode::Clamp => i.clamp(0, n - 1) as usize
+        }
+    }
+}
+
+// png image sampled with bilinear filtering, texels are converted from srgb to linear when loaded
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    texels: Vec<Vec3>,
+    pub wrap: WrapMode
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, texels: Vec<Vec3>, wrap: WrapMode) -> Result<ImageTexture> {
+        if width == 0 || height == 0 || texels.len() != width * height {
+            return Err(Error::ImageSize { width, height, texels: texels.len() })
+        }
+
+        Ok(ImageTexture { width, height, texels, wrap })
+    }
+
+    pub fn load(path: &Path, wrap: WrapMode) -> Result<ImageTexture> {
+        let file = File::open(path).map_err(|e| Error::io(path, e))?;
+
+        let mut decoder = png::Decoder::new(file);
+        // palettes and low bit depths get expanded, 16 bit channels cut to 8
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+        let mut reader = decoder.read_info().map_err(|e| Error::invalid(path, e))?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|e| Error::invalid(path, e))?;
+
+        let channels = info.color_type.samples();
+        let texels = buf[..info.buffer_size()].chunks(channels).map(|px| {
+            match info.color_type {
+                png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+                    let l = srgb_to_linear(px[0]);
+                    Vec3::new(l, l, l)
+                },
+                _ => Vec3::new(srgb_to_linear(px[0]), srgb_to_linear(px[1]), srgb_to_linear(px[2]))
+            }
+        }).collect();
+
+        ImageTexture::new(info.width as usize, info.height as usize, texels, wrap).map_err(|e| Error::invalid(path, e))
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        self.texels[self.wrap.apply(y, self.height) * self.width + self.wrap.apply(x, self.width)]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Vec3) -> Vec3 {
+        // v = 0 is the bottom row of the image, texel centers sit at half integer coordinates
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+
+        if !x.is_finite() || !y.is_finite() {
+            return Vec3::new(0.0, 0.0, 0.0)
+        }
+
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = (1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0);
+        let bottom = (1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1);
+
+        (1.0 - fy) * top + fy * bottom
+    }
+}
+
+fn srgb_to_linear(c: u8) -> f64 {
+    let c = c as f64 / 255.0;
+
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
 }
\ No newline at end of file
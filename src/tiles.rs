@@ -0,0 +1,95 @@
+use std::f64::consts::PI;
+
+use clap::ValueEnum;
+
+// rectangle of pixels, [x0, x1) by [y0, y1) with y counting rows from the top of the image
+#[derive(Clone, Copy, Debug)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32
+}
+
+impl Tile {
+    pub fn width(&self) -> u32 { self.x1 - self.x0 }
+    pub fn height(&self) -> u32 { self.y1 - self.y0 }
+    pub fn pixels(&self) -> usize { (self.width() * self.height()) as usize }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TileOrder {
+    /// row by row from the top left
+    Scanline,
+    /// outwards from the center of the image, the interesting part usually shows up first
+    Spiral,
+    /// along a hilbert curve, neighbouring tiles are rendered close together in time
+    Hilbert
+}
+
+// splits the image into `size` x `size` tiles (smaller along the right and bottom edges) in the order
+// they should be handed out in
+pub fn tiles(width: u32, height: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let columns = width.div_ceil(size);
+    let rows = height.div_ceil(size);
+
+    let mut grid: Vec<(u32, u32)> = (0..rows).flat_map(|ty| (0..columns).map(move |tx| (tx, ty))).collect();
+
+    match order {
+        TileOrder::Scanline => (),
+        TileOrder::Spiral => {
+            let cx = (columns as f64 - 1.0) / 2.0;
+            let cy = (rows as f64 - 1.0) / 2.0;
+
+            // ring around the center first, then the angle within the ring
+            let key = |&(tx, ty): &(u32, u32)| {
+                let (dx, dy) = (tx as f64 - cx, ty as f64 - cy);
+                let ring = dx.abs().max(dy.abs()).round() as u64;
+                let angle = (dy.atan2(dx) + PI) / (2.0 * PI);
+
+                (ring, (angle * u32::MAX as f64) as u64)
+            };
+
+            grid.sort_by_key(key);
+        },
+        TileOrder::Hilbert => {
+            let n = columns.max(rows).next_power_of_two();
+            grid.sort_by_key(|&(tx, ty)| hilbert_index(n, tx, ty));
+        }
+    }
+
+    grid.into_iter().map(|(tx, ty)| Tile {
+        x0: tx * size,
+        y0: ty * size,
+        x1: ((tx + 1) * size).min(width),
+        y1: ((ty + 1) * size).min(height)
+    }).collect()
+}
+
+// distance along the hilbert curve filling an n x n grid, n a power of two
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d: u64 = 0;
+    let mut s = n / 2;
+
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+
+        d += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+
+        // rotate the quadrant so the curve inside it lines up with the next level
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+
+            std::mem::swap(&mut x, &mut y);
+        }
+
+        s /= 2;
+    }
+
+    d
+}
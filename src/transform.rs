@@ -0,0 +1,246 @@
+use std::{sync::Arc, ops::Mul};
+
+use crate::{vec3::{Vec3, Point3, unit_vector}, ray::Ray, hittable::{Hit, HitRecord}, aabb::AABB, sampler::Sampler, error::{Error, Result}};
+
+// row major affine transform, the last row is only kept so inverting is the usual 4x4 inverse
+#[derive(Debug, Clone, Copy)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4]
+}
+
+impl Matrix4 {
+    pub fn identity() -> Matrix4 {
+        Matrix4::scale(Vec3::new(1.0, 1.0, 1.0))
+    }
+
+    pub fn translate(offset: Vec3) -> Matrix4 {
+        Matrix4 {
+            m: [
+                [1.0, 0.0, 0.0, offset.x],
+                [0.0, 1.0, 0.0, offset.y],
+                [0.0, 0.0, 1.0, offset.z],
+                [0.0, 0.0, 0.0, 1.0]
+            ]
+        }
+    }
+
+    pub fn scale(factor: Vec3) -> Matrix4 {
+        Matrix4 {
+            m: [
+                [factor.x, 0.0, 0.0, 0.0],
+                [0.0, factor.y, 0.0, 0.0],
+                [0.0, 0.0, factor.z, 0.0],
+                [0.0, 0.0, 0.0, 1.0]
+            ]
+        }
+    }
+
+    // counter clockwise rotation around `axis` by `degrees`
+    pub fn rotate(axis: Vec3, degrees: f64) -> Matrix4 {
+        let a = unit_vector(&axis);
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let t = 1.0 - cos;
+
+        Matrix4 {
+            m: [
+                [t * a.x * a.x + cos, t * a.x * a.y - sin * a.z, t * a.x * a.z + sin * a.y, 0.0],
+                [t * a.x * a.y + sin * a.z, t * a.y * a.y + cos, t * a.y * a.z - sin * a.x, 0.0],
+                [t * a.x * a.z - sin * a.y, t * a.y * a.z + sin * a.x, t * a.z * a.z + cos, 0.0],
+                [0.0, 0.0, 0.0, 1.0]
+            ]
+        }
+    }
+
+    // scale first, then rotate around x, y and z in that order (degrees), then translate
+    pub fn from_trs(translation: Vec3, rotation: Vec3, scale: Vec3) -> Matrix4 {
+        Matrix4::translate(translation)
+            * Matrix4::rotate(Vec3::new(0.0, 0.0, 1.0), rotation.z)
+            * Matrix4::rotate(Vec3::new(0.0, 1.0, 0.0), rotation.y)
+            * Matrix4::rotate(Vec3::new(1.0, 0.0, 0.0), rotation.x)
+            * Matrix4::scale(scale)
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+
+        Matrix4 { m }
+    }
+
+    // gauss-jordan with partial pivoting, None for singular matrices (e.g. a scale of 0)
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inv = Matrix4::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|x, y| a[*x][col].abs().total_cmp(&a[*y][col].abs())).unwrap();
+
+            if a[pivot][col].abs() < 1e-12 {
+                return None
+            }
+
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+
+            for row in 0..4 {
+                if row == col {
+                    continue
+                }
+
+                let factor = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[col][j];
+                    inv[row][j] -= factor * inv[col][j];
+                }
+            }
+        }
+
+        Some(Matrix4 { m: inv })
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        let m = &self.m;
+
+        Point3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3]
+        )
+    }
+
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z
+        )
+    }
+
+    // normals go through the inverse transpose, called on the inverse so that's just the transpose
+    pub fn transform_normal(&self, n: &Vec3) -> Vec3 {
+        self.transpose().transform_vector(n)
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Self::Output {
+        let mut m = [[0.0; 4]; 4];
+
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+
+        Matrix4 { m }
+    }
+}
+
+// Places `object` in the world through `to_world`. The object is shared so the same mesh bvh can be
+// instanced any number of times without copying its triangles
+pub struct Transform {
+    pub object: Arc<dyn Hit>,
+    to_world: Matrix4,
+    to_object: Matrix4
+}
+
+impl Transform {
+    pub fn new(object: Arc<dyn Hit>, to_world: Matrix4) -> Result<Transform> {
+        let to_object = to_world.inverse().ok_or(Error::SingularTransform)?;
+
+        Ok(Transform { object, to_world, to_object })
+    }
+
+    // the direction isn't renormalized, so t means the same thing in both spaces
+    fn to_object_ray(&self, r: &Ray) -> Ray {
+        Ray::new(self.to_object.transform_point(&r.origin), self.to_object.transform_vector(&r.direction), r.time)
+    }
+}
+
+impl Hit for Transform {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut rec = self.object.hit(&self.to_object_ray(r), t_min, t_max)?;
+
+        // dot products between normals and directions keep their sign, so front_face still holds
+        rec.p = self.to_world.transform_point(&rec.p);
+        rec.normal = unit_vector(&self.to_object.transform_normal(&rec.normal));
+
+        Some(rec)
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.object.occluded(&self.to_object_ray(r), t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        let bbox = self.object.bounding_box(time0, time1)?;
+
+        let corners = (0..8).map(|i| {
+            let corner = Point3::new(
+                if i & 1 == 0 { bbox.minimum.x } else { bbox.maximum.x },
+                if i & 2 == 0 { bbox.minimum.y } else { bbox.maximum.y },
+                if i & 4 == 0 { bbox.minimum.z } else { bbox.maximum.z }
+            );
+
+            let p = self.to_world.transform_point(&corner);
+            AABB::new(&p, &p)
+        });
+
+        corners.reduce(|acc, b| AABB::surrounding_box(&acc, &b))
+    }
+
+    // solid angles are only preserved by rotations, translations and uniform scales, with a non uniform
+    // scale light sampling through a transform is biased
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.object.pdf_value(&self.to_object.transform_point(origin), &self.to_object.transform_vector(direction))
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.to_world.transform_vector(&self.object.random(&self.to_object.transform_point(origin), sampler))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_identity(m: &Matrix4) {
+        for (i, row) in m.m.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((value - expected).abs() < 1e-9, "m[{}][{}] = {}", i, j, value);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let m = Matrix4::from_trs(Vec3::new(3.0, -2.0, 10.0), Vec3::new(30.0, 45.0, -60.0), Vec3::new(2.0, 0.5, 4.0));
+        let inverse = m.inverse().unwrap();
+
+        assert_identity(&(m * inverse));
+        assert_identity(&(inverse * m));
+    }
+
+    #[test]
+    fn inverse_of_a_zero_scale_is_none() {
+        let m = Matrix4::from_trs(Vec3::new(1.0, 2.0, 3.0), Vec3::new(0.0, 90.0, 0.0), Vec3::new(1.0, 0.0, 1.0));
+
+        assert!(m.inverse().is_none());
+    }
+}
@@ -1,46 +1,39 @@
 use std::sync::Arc;
 
-use crate::{material::Scatter, vec3::{Vec3, cross_product}, hittable::{Hit, HitRecord}, aabb::AABB, ray::Ray};
+use crate::{material::Scatter, vec3::{Vec3, Point3, cross_product, unit_vector, dot_product}, hittable::{Hit, HitRecord}, aabb::AABB, ray::Ray, sampler::Sampler};
 
 pub struct Triangle {
     pub v0: Vec3,
     pub v1: Vec3,
     pub v2: Vec3,
     pub normal: Vec3,
+    // unit normals at v0, v1 and v2 for smooth shading, the face normal is used when there are none
+    pub vertex_normals: Option<[Vec3; 3]>,
+    // texture coordinates at v0, v1 and v2, without them the barycentrics are reported as u and v
+    pub uvs: Option<[(f64, f64); 3]>,
     pub material: Arc<dyn Scatter>
 }
 
 impl Triangle {
     pub fn new(v0: Vec3, v1: Vec3, v2: Vec3, material: Arc<dyn Scatter>) -> Triangle {
         Triangle {
-            v0, v1, v2, normal: cross_product(&(v1 - v0), &(v2 - v0)), material
+            v0, v1, v2, normal: unit_vector(&cross_product(&(v1 - v0), &(v2 - v0))), vertex_normals: None, uvs: None, material
         }
     }
 
-    pub fn new_with_normal(v0: Vec3, v1: Vec3, v2: Vec3, normal: Vec3, material: Arc<dyn Scatter>) -> Triangle {
-        Triangle {
-            v0, v1, v2, normal, material
+    pub fn new_with_normals(v0: Vec3, v1: Vec3, v2: Vec3, normals: [Vec3; 3], material: Arc<dyn Scatter>) -> Triangle {
+        let mut tri = Triangle::new(v0, v1, v2, material);
+
+        // a zero normal from a bad mesh would turn every scattered direction into NaN, stay flat instead
+        if normals.iter().all(|n| !n.near_zero()) {
+            tri.vertex_normals = Some(normals.map(|n| unit_vector(&n)));
         }
-    }
-}
 
-impl Hit for Triangle {
-    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
-        Some(AABB::new(
-            &Vec3::new(
-                self.v0.x.min(self.v1.x.min(self.v2.x)),
-                self.v0.y.min(self.v1.y.min(self.v2.y)),
-                self.v0.z.min(self.v1.z.min(self.v2.z))
-            ),
-            &Vec3::new(
-                self.v0.x.max(self.v1.x.max(self.v2.x)),
-                self.v0.y.max(self.v1.y.max(self.v2.y)),
-                self.v0.z.max(self.v1.z.max(self.v2.z))
-            )
-        ))
+        tri
     }
 
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    // moller-trumbore, returns t and the barycentric coordinates of the hit
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
         let v0v1 = self.v1 - self.v0;
         let v0v2 = self.v2 - self.v0;
 
@@ -71,16 +64,101 @@ impl Hit for Triangle {
             return None
         }
 
+        Some((t, u, v))
+    }
+}
+
+impl Hit for Triangle {
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        // axis aligned triangles (walls, floors, area lights) would get a box with no thickness,
+        // which AABB::hit can never report as hit, so pad every axis a tiny bit
+        const PAD: f64 = 1e-4;
+
+        Some(AABB::new(
+            &Vec3::new(
+                self.v0.x.min(self.v1.x.min(self.v2.x)) - PAD,
+                self.v0.y.min(self.v1.y.min(self.v2.y)) - PAD,
+                self.v0.z.min(self.v1.z.min(self.v2.z)) - PAD
+            ),
+            &Vec3::new(
+                self.v0.x.max(self.v1.x.max(self.v2.x)) + PAD,
+                self.v0.y.max(self.v1.y.max(self.v2.y)) + PAD,
+                self.v0.z.max(self.v1.z.max(self.v2.z)) + PAD
+            )
+        ))
+    }
+
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (t, u, v) = self.intersect(r, t_min, t_max)?;
+
         let p = r.at(t);
 
-        return Some(HitRecord {
-            u,
-            v,
+        let (tex_u, tex_v) = match &self.uvs {
+            Some([uv0, uv1, uv2]) => (
+                (1.0 - u - v) * uv0.0 + u * uv1.0 + v * uv2.0,
+                (1.0 - u - v) * uv0.1 + u * uv1.1 + v * uv2.1
+            ),
+            None => (u, v)
+        };
+
+        let mut rec = HitRecord {
+            u: tex_u,
+            v: tex_v,
             t,
             p,
             normal: self.normal,
             mat: self.material.clone(),
             front_face: false
-        })
+        };
+
+        // which side was hit is a question for the actual surface, the interpolated normal can disagree
+        // at grazing angles and would send a dielectric the wrong way
+        rec.set_face_normal(r, &self.normal);
+
+        if let Some([n0, n1, n2]) = &self.vertex_normals {
+            let shading = (1.0 - u - v) * n0 + u * n1 + v * n2;
+
+            // opposing vertex normals can cancel out inside the face, keep the flat normal there
+            if !shading.near_zero() {
+                let shading = unit_vector(&shading);
+                rec.normal = if dot_product(&shading, &rec.normal) < 0.0 { -shading } else { shading };
+            }
+        }
+
+        Some(rec)
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    // uniform over the triangle's area, converted to solid angle as seen from origin
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let Some(rec) = self.hit(&Ray::new(*origin, *direction, 0.0), 0.001, f64::INFINITY) else {
+            return 0.0
+        };
+
+        let n = cross_product(&(self.v1 - self.v0), &(self.v2 - self.v0));
+        let area = 0.5 * n.length();
+        let distance_squared = rec.t * rec.t * direction.length_squared();
+        let cosine = (dot_product(direction, &n) / (direction.length() * n.length())).abs();
+
+        if cosine < 1e-8 {
+            return 0.0
+        }
+
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (mut a, mut b) = sampler.next_2d();
+
+        // fold the square onto the triangle
+        if a + b > 1.0 {
+            a = 1.0 - a;
+            b = 1.0 - b;
+        }
+
+        self.v0 + a * (self.v1 - self.v0) + b * (self.v2 - self.v0) - origin
     }
 }
\ No newline at end of file
@@ -1,6 +1,5 @@
 use std::ops::{Neg, Index, IndexMut, AddAssign, MulAssign, DivAssign ,Add, Sub, Mul, Div, RangeInclusive};
-use crate::color::Color;
-use rand::rngs::ThreadRng;
+use crate::{color::Color, random::RandomStream, sampler::Sampler};
 use rand::Rng;
 
 use std::simd::{f64x4, Simd};
@@ -58,7 +57,7 @@ impl Vec3 {
         (self[0], self[1], self[2])
     }
 
-    pub fn random(rng: &mut ThreadRng) -> Vec3 {
+    pub fn random(rng: &mut RandomStream) -> Vec3 {
         Vec3::new(
             rng.gen::<f64>(),
             rng.gen::<f64>(),
@@ -66,7 +65,7 @@ impl Vec3 {
         )
     }
 
-    pub fn random_in_range(rng: &mut ThreadRng, range: RangeInclusive<f64>) -> Vec3 {
+    pub fn random_in_range(rng: &mut RandomStream, range: RangeInclusive<f64>) -> Vec3 {
         Vec3::new(
             rng.gen_range(range.clone()),
             rng.gen_range(range.clone()),
@@ -117,7 +116,7 @@ pub fn cross_product(v1: &Vec3, v2: &Vec3) -> Vec3 {
     }
 }
 
-pub fn random_in_unit_sphere(rng: &mut ThreadRng) -> Vec3 {
+pub fn random_in_unit_sphere(rng: &mut RandomStream) -> Vec3 {
     loop {
         let p: Vec3 = Vec3::random_in_range(rng, -1.0..=1.0);
 
@@ -129,11 +128,18 @@ pub fn random_in_unit_sphere(rng: &mut ThreadRng) -> Vec3 {
     }
 }
 
-pub fn random_unit_vector(rng: &mut ThreadRng) -> Vec3 {
-    unit_vector(&random_in_unit_sphere(rng))
+// uniform over the whole sphere
+pub fn random_unit_vector(sampler: &mut dyn Sampler) -> Vec3 {
+    let (r1, r2) = sampler.next_2d();
+
+    let z = 1.0 - 2.0 * r2;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * r1;
+
+    Vec3::new(phi.cos() * r, phi.sin() * r, z)
 }
 
-pub fn random_in_hemisphere(rng: &mut ThreadRng, normal: &Vec3) -> Vec3 {
+pub fn random_in_hemisphere(rng: &mut RandomStream, normal: &Vec3) -> Vec3 {
     let in_unit_sphere = random_in_unit_sphere(rng);
 
     if dot_product(&in_unit_sphere, normal) > 0.0 {
@@ -143,6 +149,29 @@ pub fn random_in_hemisphere(rng: &mut ThreadRng, normal: &Vec3) -> Vec3 {
     }
 }
 
+// cosine weighted direction around +z
+pub fn random_cosine_direction(sampler: &mut dyn Sampler) -> Vec3 {
+    let (r1, r2) = sampler.next_2d();
+
+    let phi = 2.0 * std::f64::consts::PI * r1;
+    let x = phi.cos() * r2.sqrt();
+    let y = phi.sin() * r2.sqrt();
+    let z = (1.0 - r2).sqrt();
+
+    Vec3::new(x, y, z)
+}
+
+// direction around +z distributed as cos^exponent
+pub fn random_phong_direction(sampler: &mut dyn Sampler, exponent: f64) -> Vec3 {
+    let (r1, r2) = sampler.next_2d();
+
+    let phi = 2.0 * std::f64::consts::PI * r1;
+    let z = r2.powf(1.0 / (exponent + 1.0));
+    let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+
+    Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z)
+}
+
 pub fn reflect(vec1: &Vec3, vec2: &Vec3) -> Vec3 {
     vec1 - 2.0 * dot_product(&vec1, &vec2) * vec2
 }
@@ -155,16 +184,23 @@ pub fn refract(uv: &Vec3, n: &Vec3, e: f64) -> Vec3 {
     perp + parr
 }
 
-pub fn random_in_unit_disk(rng: &mut ThreadRng) -> Vec3 {
-    loop {
-        let p = Vec3::new(rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0), 0.0);
-
-        if p.length_squared() >= 1.0 {
-            continue;
-        }
+// shirley-chiu concentric mapping, keeps the square's strata intact unlike rejection sampling
+pub fn random_in_unit_disk(sampler: &mut dyn Sampler) -> Vec3 {
+    let (r1, r2) = sampler.next_2d();
+    let (a, b) = (2.0 * r1 - 1.0, 2.0 * r2 - 1.0);
 
-        return p
+    if a == 0.0 && b == 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0)
     }
+
+    let quarter = std::f64::consts::FRAC_PI_4;
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, quarter * (b / a))
+    } else {
+        (b, 2.0 * quarter - quarter * (a / b))
+    };
+
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
 }
 
 impl Neg for Vec3 {